@@ -15,3 +15,10 @@ gdt_flush:
 	jmp 0x08:.flush   ; 0x08 is the offset to our code segment: Far jump!
 .flush:
 	ret
+
+global tss_flush
+
+tss_flush:
+	mov ax, [esp+4]   ; Get the TSS selector, passed as a parameter.
+	ltr ax            ; Load the task register
+	ret
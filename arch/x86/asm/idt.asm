@@ -74,6 +74,17 @@ IRQ 13, 45
 IRQ 14, 46
 IRQ 15, 47
 
+ISR_NOERRCODE 48          ; Voluntary reschedule, see cpu::yield_interrupt
+
+; System calls from ring 3. A byte push would sign extend 128, so this one
+; spells out the dword pushes.
+global isr128
+isr128:
+	cli
+	push dword 0
+	push dword 128
+	jmp isr_common_stub
+
 global idt_flush      ; Allows the C code to call idt_flush().
 
 idt_flush:
@@ -81,6 +92,15 @@ idt_flush:
 	lidt [eax]        ; Load the IDT pointer.
 	ret
 
+extern double_fault_handler
+global double_fault_entry
+
+; Entered through the task gate for vector 8 on the fault task's own stack.
+; The CPU has pushed the error code, which becomes the handler's argument.
+double_fault_entry:
+	call double_fault_handler
+	jmp double_fault_entry
+
 extern isr_handler
 
 ; This is our common ISR stub. It saves the processor state, sets
@@ -104,7 +124,9 @@ isr_common_stub:
     fxsave [esp]
     push eax
 
+    push esp                  ; Pointer to the saved context, see cpu::Context
 	call isr_handler
+    mov esp, eax              ; Resume the context the handler returned
 
     pop eax
     fxrstor [esp]
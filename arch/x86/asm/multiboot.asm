@@ -5,5 +5,22 @@ section .multiboot
     ;multiboot spec
     align 4
     dd 0x1BADB002            ;magic
-    dd 0x00                  ;flags
-    dd - (0x1BADB002 + 0x00) ;checksum. m+f+c should be zero
+    dd 0x03                  ;flags: page align modules, provide memory map
+    dd - (0x1BADB002 + 0x03) ;checksum. m+f+c should be zero
+
+    ; Multiboot2 header, for loaders that prefer the newer protocol. The ELF
+    ; headers already tell where to load the kernel, the only request is to
+    ; page align modules like the flags above do.
+    align 8
+multiboot2_header:
+    dd 0xE85250D6            ;magic
+    dd 0                     ;architecture: 32-bit protected mode i386
+    dd multiboot2_header_end - multiboot2_header
+    dd 0x100000000 - (0xE85250D6 + 0 + (multiboot2_header_end - multiboot2_header)) ;checksum
+    dw 6                     ;module alignment tag
+    dw 0
+    dd 8
+    dw 0                     ;end tag
+    dw 0
+    dd 8
+multiboot2_header_end:
@@ -5,9 +5,12 @@ __morestack:
 	hlt
 	jmp __morestack
 
-; Allocate a 16KiB stack
-section .bootstrap_stack
-align 4
+; Allocate a 16KiB stack, preceded by a guard page that is unmapped once
+; paging is on so that an overflow faults instead of corrupting memory
+section .bootstrap_stack write align=4096
+global stack_guard
+stack_guard:
+times 4096 db 0
 stack_bottom:
 times 16384 db 0
 stack_top:
@@ -23,6 +26,9 @@ start:
 	mov esp, stack_top
 	; Make everything play nice with segmented stacks - see __morestack below
 	mov [gs:0x30], dword 0
+	; entry(magic, info) gets the boot loader magic and information pointer
+	push ebx
+	push eax
 	call entry
 	jmp hang
 
@@ -0,0 +1,106 @@
+/*
+ * Saved execution contexts
+ *
+ * isr_common_stub leaves everything it saved on the interrupted stack: the
+ * interrupt frame, the general registers and the FPU/SSE state. A pointer to
+ * that block is all that is needed to resume the interrupted code later, so
+ * switching threads means returning a different pointer from isr_handler.
+ */
+
+use core::mem;
+use super::{InterruptArguments, KERNEL_CODE_SELECTOR, KERNEL_DATA_SELECTOR, USER_CODE_SELECTOR, USER_DATA_SELECTOR};
+
+const FXSAVE_SIZE: usize = 512;
+
+/// Layout of the block isr_common_stub passes to isr_handler
+#[repr(C)]
+pub struct Context
+{
+	pub args: *mut InterruptArguments,
+	pub fpu_sse: [u8; FXSAVE_SIZE]
+}
+
+/// Builds a context at the top of a fresh kernel stack that starts executing
+/// `entry` with interrupts enabled when it is resumed
+pub fn new_context(stack_top: u32, entry: u32) -> *mut Context
+{
+	// A ring 0 iret does not pop useresp and ss, those slots are just unused
+	build_context(stack_top, InterruptArguments {
+		ds: KERNEL_DATA_SELECTOR, edi: 0, esi: 0, ebp: 0, esp: 0, ebx: 0, edx: 0, ecx: 0, eax: 0,
+		interrupt_number: 0,
+		error_code: 0,
+		eip: entry, cs: KERNEL_CODE_SELECTOR, eflags: 0x202, useresp: 0, ss: 0,
+	})
+}
+
+/// Builds a context on a fresh kernel stack whose iret drops to ring 3 at
+/// `entry` with `user_stack` as the stack pointer. `kernel_stack_top` has to
+/// be installed with `set_kernel_stack` whenever the context runs.
+pub fn new_user_context(kernel_stack_top: u32, entry: u32, user_stack: u32) -> *mut Context
+{
+	build_context(kernel_stack_top, InterruptArguments {
+		ds: USER_DATA_SELECTOR, edi: 0, esi: 0, ebp: 0, esp: 0, ebx: 0, edx: 0, ecx: 0, eax: 0,
+		interrupt_number: 0,
+		error_code: 0,
+		// IOPL stays 0, so port I/O from user code faults
+		eip: entry, cs: USER_CODE_SELECTOR, eflags: 0x202, useresp: user_stack, ss: USER_DATA_SELECTOR,
+	})
+}
+
+/// Builds a context on a fresh kernel stack that resumes with the registers in
+/// `frame`, except that EAX holds `eax`, and with a copy of the FPU/SSE state
+/// saved along with `frame`. `frame` has to belong to an interrupt that is
+/// still being handled.
+pub fn new_context_from_frame(stack_top: u32, frame: &InterruptArguments, eax: u32) -> *mut Context
+{
+	let context = build_context(stack_top, InterruptArguments { eax: eax, .. *frame });
+	unsafe { (*context).fpu_sse = *saved_fpu_state(frame); }
+	context
+}
+
+// isr_common_stub puts the FPU/SSE state right below the frame, aligned the
+// same way build_context does it
+fn saved_fpu_state(frame: &InterruptArguments) -> &[u8; FXSAVE_SIZE]
+{
+	let address = (frame as *const InterruptArguments as u32 - FXSAVE_SIZE as u32) & !0xF;
+	unsafe { &*(address as *const [u8; FXSAVE_SIZE]) }
+}
+
+/// Kernel stack top as the CPU should see it in esp0, matching where the
+/// frames built here end
+pub fn stack_top(stack_end: u32) -> u32
+{
+	stack_end & !0xF
+}
+
+fn build_context(stack_end: u32, frame: InterruptArguments) -> *mut Context
+{
+	unsafe
+	{
+		let args_address = stack_top(stack_end) - mem::size_of::<InterruptArguments>() as u32;
+		let args = args_address as *mut InterruptArguments;
+		*args = frame;
+
+		// fxrstor needs a 16 byte aligned area directly above the args pointer
+		let fpu_address = (args_address - FXSAVE_SIZE as u32) & !0xF;
+		let context = (fpu_address - 4) as *mut Context;
+		(*context).args = args;
+		init_fpu_state(&mut (*context).fpu_sse);
+		context
+	}
+}
+
+/// The state fninit leaves behind, with all SSE exceptions masked
+fn init_fpu_state(state: &mut [u8; FXSAVE_SIZE])
+{
+	for byte in state.iter_mut()
+	{
+		*byte = 0;
+	}
+	// FCW
+	state[0] = 0x7F;
+	state[1] = 0x03;
+	// MXCSR
+	state[24] = 0x80;
+	state[25] = 0x1F;
+}
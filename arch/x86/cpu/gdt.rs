@@ -6,10 +6,25 @@
 
 use core::marker::Copy;
 use core::clone::Clone;
+use kernel::sync::Spinlock;
+use super::tss;
 
-const GDT_COUNT: usize = 5;
-static mut gdt_entries: [GDTEntry; GDT_COUNT] = [GDTEntry { limit_low: 0, base_low: 0, base_middle: 0, access: 0, granularity: 0, base_high: 0 }; GDT_COUNT];
-static mut gdt_ptr: GDTPointer = GDTPointer { limit: 0, base: 0 };
+const GDT_COUNT: usize = 7;
+pub const KERNEL_TSS_SELECTOR: u16 = 0x28;
+pub const FAULT_TSS_SELECTOR: u16 = 0x30;
+
+struct Gdt
+{
+	entries: [GDTEntry; GDT_COUNT],
+	pointer: GDTPointer
+}
+
+// The CPU keeps using the table after lgdt, so it lives in a static
+static GDT: Spinlock<Gdt> = spinlock!(Gdt
+{
+	entries: [GDTEntry { limit_low: 0, base_low: 0, base_middle: 0, access: 0, granularity: 0, base_high: 0 }; GDT_COUNT],
+	pointer: GDTPointer { limit: 0, base: 0 }
+});
 
 #[repr(packed)]
 struct GDTEntry
@@ -34,35 +49,46 @@ struct GDTPointer
 
 pub fn init_gdt()
 {
+	let mut gdt = GDT.lock();
+	gdt.pointer.limit = (::core::mem::size_of::<GDTEntry>() * GDT_COUNT - 1) as u16;
+	gdt.pointer.base = &gdt.entries as *const [GDTEntry; GDT_COUNT] as usize;
+
+	gdt.set_gate(0, 0, 0, 0, 0);
+	gdt.set_gate(1, 0, 0xFFFFFFFF, 0x9A, 0xCF);
+	gdt.set_gate(2, 0, 0xFFFFFFFF, 0x92, 0xCF);
+	gdt.set_gate(3, 0, 0xFFFFFFFF, 0xFA, 0xCF);
+	gdt.set_gate(4, 0, 0xFFFFFFFF, 0xF2, 0xCF);
+
 	unsafe
 	{
-		gdt_ptr.limit = (::core::mem::size_of::<GDTEntry>() * GDT_COUNT - 1) as u16;
-		gdt_ptr.base = &gdt_entries as *const [GDTEntry; GDT_COUNT] as usize;
+		tss::init_tss();
+		gdt.set_gate(5, &tss::kernel_tss as *const tss::TaskStateSegment as usize, tss::size() - 1, 0x89, 0x00);
+		gdt.set_gate(6, &tss::fault_tss as *const tss::TaskStateSegment as usize, tss::size() - 1, 0x89, 0x00);
 
-		gdt_set_gate(0, 0, 0, 0, 0);
-		gdt_set_gate(1, 0, 0xFFFFFFFF, 0x9A, 0xCF);
-		gdt_set_gate(2, 0, 0xFFFFFFFF, 0x92, 0xCF);
-		gdt_set_gate(3, 0, 0xFFFFFFFF, 0xFA, 0xCF);
-		gdt_set_gate(4, 0, 0xFFFFFFFF, 0xF2, 0xCF);
-
-		gdt_flush(&gdt_ptr as *const GDTPointer as u32);
-	};
+		gdt_flush(&gdt.pointer as *const GDTPointer as u32);
+		tss_flush(KERNEL_TSS_SELECTOR);
+	}
 }
 
-unsafe fn gdt_set_gate(n: usize, base: usize, limit: usize, access: u8, gran: u8)
+impl Gdt
 {
-	gdt_entries[n].base_low = (base & 0xFFFF) as u16;
-	gdt_entries[n].base_middle = ((base >> 16) & 0xFF) as u8;
-	gdt_entries[n].base_high = ((base >> 24) & 0xFF) as u8;
+	fn set_gate(&mut self, n: usize, base: usize, limit: usize, access: u8, gran: u8)
+	{
+		let entry = &mut self.entries[n];
+		entry.base_low = (base & 0xFFFF) as u16;
+		entry.base_middle = ((base >> 16) & 0xFF) as u8;
+		entry.base_high = ((base >> 24) & 0xFF) as u8;
 
-	gdt_entries[n].limit_low = (limit & 0xFFFF) as u16;
-	gdt_entries[n].granularity = ((limit >> 16) & 0x0F) as u8;
+		entry.limit_low = (limit & 0xFFFF) as u16;
+		entry.granularity = ((limit >> 16) & 0x0F) as u8;
 
-	gdt_entries[n].granularity |= gran & 0xF0;
-	gdt_entries[n].access = access;
+		entry.granularity |= gran & 0xF0;
+		entry.access = access;
+	}
 }
 
 extern
 {
 	fn gdt_flush(pointer: u32);
+	fn tss_flush(selector: u16);
 }
@@ -6,10 +6,22 @@
 
 use core::marker::Copy;
 use core::clone::Clone;
+use kernel::sync::Spinlock;
+use super::gdt;
 
 const IDT_COUNT: usize = 256;
-static mut idt_entries: [IDTEntry; IDT_COUNT] = [IDTEntry { base_low: 0, selector: 0, zero: 0, flags: 0, base_high: 0 }; IDT_COUNT];
-static mut idt_ptr: IDTPointer = IDTPointer { limit: 0, base: 0 };
+
+struct Idt
+{
+	entries: [IDTEntry; IDT_COUNT],
+	pointer: IDTPointer
+}
+
+static IDT: Spinlock<Idt> = spinlock!(Idt
+{
+	entries: [IDTEntry { base_low: 0, selector: 0, zero: 0, flags: 0, base_high: 0 }; IDT_COUNT],
+	pointer: IDTPointer { limit: 0, base: 0 }
+});
 
 #[repr(packed)]
 struct IDTEntry
@@ -33,72 +45,89 @@ struct IDTPointer
 
 pub fn init_idt()
 {
-	unsafe
-	{
-		idt_ptr.limit = (::core::mem::size_of::<IDTEntry>() * IDT_COUNT - 1) as u16;
-		idt_ptr.base = &idt_entries as *const [IDTEntry; IDT_COUNT] as usize;
+	let mut idt = IDT.lock();
+	idt.pointer.limit = (::core::mem::size_of::<IDTEntry>() * IDT_COUNT - 1) as u16;
+	idt.pointer.base = &idt.entries as *const [IDTEntry; IDT_COUNT] as usize;
 
-		idt_set_gate( 0, isr0  as usize, 0x08, 0x8E);
-		idt_set_gate( 1, isr1  as usize, 0x08, 0x8E);
-		idt_set_gate( 2, isr2  as usize, 0x08, 0x8E);
-		idt_set_gate( 3, isr3  as usize, 0x08, 0x8E);
-		idt_set_gate( 4, isr4  as usize, 0x08, 0x8E);
-		idt_set_gate( 5, isr5  as usize, 0x08, 0x8E);
-		idt_set_gate( 6, isr6  as usize, 0x08, 0x8E);
-		idt_set_gate( 7, isr7  as usize, 0x08, 0x8E);
-		idt_set_gate( 8, isr8  as usize, 0x08, 0x8E);
-		idt_set_gate( 9, isr9  as usize, 0x08, 0x8E);
-		idt_set_gate(10, isr10 as usize, 0x08, 0x8E);
-		idt_set_gate(11, isr11 as usize, 0x08, 0x8E);
-		idt_set_gate(12, isr12 as usize, 0x08, 0x8E);
-		idt_set_gate(13, isr13 as usize, 0x08, 0x8E);
-		idt_set_gate(14, isr14 as usize, 0x08, 0x8E);
-		idt_set_gate(15, isr15 as usize, 0x08, 0x8E);
-		idt_set_gate(16, isr16 as usize, 0x08, 0x8E);
-		idt_set_gate(17, isr17 as usize, 0x08, 0x8E);
-		idt_set_gate(18, isr18 as usize, 0x08, 0x8E);
-		idt_set_gate(19, isr19 as usize, 0x08, 0x8E);
-		idt_set_gate(20, isr20 as usize, 0x08, 0x8E);
-		idt_set_gate(21, isr21 as usize, 0x08, 0x8E);
-		idt_set_gate(22, isr22 as usize, 0x08, 0x8E);
-		idt_set_gate(23, isr23 as usize, 0x08, 0x8E);
-		idt_set_gate(24, isr24 as usize, 0x08, 0x8E);
-		idt_set_gate(25, isr25 as usize, 0x08, 0x8E);
-		idt_set_gate(26, isr26 as usize, 0x08, 0x8E);
-		idt_set_gate(27, isr27 as usize, 0x08, 0x8E);
-		idt_set_gate(28, isr28 as usize, 0x08, 0x8E);
-		idt_set_gate(29, isr29 as usize, 0x08, 0x8E);
-		idt_set_gate(30, isr30 as usize, 0x08, 0x8E);
-		idt_set_gate(31, isr31 as usize, 0x08, 0x8E);
-		idt_set_gate(32, irq0  as usize, 0x08, 0x8E);
-		idt_set_gate(33, irq1  as usize, 0x08, 0x8E);
-		idt_set_gate(34, irq2  as usize, 0x08, 0x8E);
-		idt_set_gate(35, irq3  as usize, 0x08, 0x8E);
-		idt_set_gate(36, irq4  as usize, 0x08, 0x8E);
-		idt_set_gate(37, irq5  as usize, 0x08, 0x8E);
-		idt_set_gate(38, irq6  as usize, 0x08, 0x8E);
-		idt_set_gate(39, irq7  as usize, 0x08, 0x8E);
-		idt_set_gate(40, irq8  as usize, 0x08, 0x8E);
-		idt_set_gate(41, irq9  as usize, 0x08, 0x8E);
-		idt_set_gate(42, irq10 as usize, 0x08, 0x8E);
-		idt_set_gate(43, irq11 as usize, 0x08, 0x8E);
-		idt_set_gate(44, irq12 as usize, 0x08, 0x8E);
-		idt_set_gate(45, irq13 as usize, 0x08, 0x8E);
-		idt_set_gate(46, irq14 as usize, 0x08, 0x8E);
-		idt_set_gate(47, irq15 as usize, 0x08, 0x8E);
+	idt.set_gate( 0, isr0  as usize, 0x08, 0x8E);
+	idt.set_gate( 1, isr1  as usize, 0x08, 0x8E);
+	idt.set_gate( 2, isr2  as usize, 0x08, 0x8E);
+	idt.set_gate( 3, isr3  as usize, 0x08, 0x8E);
+	idt.set_gate( 4, isr4  as usize, 0x08, 0x8E);
+	idt.set_gate( 5, isr5  as usize, 0x08, 0x8E);
+	idt.set_gate( 6, isr6  as usize, 0x08, 0x8E);
+	idt.set_gate( 7, isr7  as usize, 0x08, 0x8E);
+	idt.set_task_gate(8, gdt::FAULT_TSS_SELECTOR);
+	idt.set_gate( 9, isr9  as usize, 0x08, 0x8E);
+	idt.set_gate(10, isr10 as usize, 0x08, 0x8E);
+	idt.set_gate(11, isr11 as usize, 0x08, 0x8E);
+	idt.set_gate(12, isr12 as usize, 0x08, 0x8E);
+	idt.set_gate(13, isr13 as usize, 0x08, 0x8E);
+	idt.set_gate(14, isr14 as usize, 0x08, 0x8E);
+	idt.set_gate(15, isr15 as usize, 0x08, 0x8E);
+	idt.set_gate(16, isr16 as usize, 0x08, 0x8E);
+	idt.set_gate(17, isr17 as usize, 0x08, 0x8E);
+	idt.set_gate(18, isr18 as usize, 0x08, 0x8E);
+	idt.set_gate(19, isr19 as usize, 0x08, 0x8E);
+	idt.set_gate(20, isr20 as usize, 0x08, 0x8E);
+	idt.set_gate(21, isr21 as usize, 0x08, 0x8E);
+	idt.set_gate(22, isr22 as usize, 0x08, 0x8E);
+	idt.set_gate(23, isr23 as usize, 0x08, 0x8E);
+	idt.set_gate(24, isr24 as usize, 0x08, 0x8E);
+	idt.set_gate(25, isr25 as usize, 0x08, 0x8E);
+	idt.set_gate(26, isr26 as usize, 0x08, 0x8E);
+	idt.set_gate(27, isr27 as usize, 0x08, 0x8E);
+	idt.set_gate(28, isr28 as usize, 0x08, 0x8E);
+	idt.set_gate(29, isr29 as usize, 0x08, 0x8E);
+	idt.set_gate(30, isr30 as usize, 0x08, 0x8E);
+	idt.set_gate(31, isr31 as usize, 0x08, 0x8E);
+	idt.set_gate(32, irq0  as usize, 0x08, 0x8E);
+	idt.set_gate(33, irq1  as usize, 0x08, 0x8E);
+	idt.set_gate(34, irq2  as usize, 0x08, 0x8E);
+	idt.set_gate(35, irq3  as usize, 0x08, 0x8E);
+	idt.set_gate(36, irq4  as usize, 0x08, 0x8E);
+	idt.set_gate(37, irq5  as usize, 0x08, 0x8E);
+	idt.set_gate(38, irq6  as usize, 0x08, 0x8E);
+	idt.set_gate(39, irq7  as usize, 0x08, 0x8E);
+	idt.set_gate(40, irq8  as usize, 0x08, 0x8E);
+	idt.set_gate(41, irq9  as usize, 0x08, 0x8E);
+	idt.set_gate(42, irq10 as usize, 0x08, 0x8E);
+	idt.set_gate(43, irq11 as usize, 0x08, 0x8E);
+	idt.set_gate(44, irq12 as usize, 0x08, 0x8E);
+	idt.set_gate(45, irq13 as usize, 0x08, 0x8E);
+	idt.set_gate(46, irq14 as usize, 0x08, 0x8E);
+	idt.set_gate(47, irq15 as usize, 0x08, 0x8E);
+	idt.set_gate(48, isr48 as usize, 0x08, 0x8E);
+	// DPL 3, so int 0x80 may be used from user mode
+	idt.set_gate(128, isr128 as usize, 0x08, 0xEE);
 
-		idt_flush(&idt_ptr as *const IDTPointer as u32);
-	}
+	unsafe { idt_flush(&idt.pointer as *const IDTPointer as u32); }
 }
 
-unsafe fn idt_set_gate(n: usize, base: usize, sel: u16, flags: u8)
+impl Idt
 {
-	idt_entries[n].base_low = (base & 0xFFFF) as u16;
-	idt_entries[n].base_high = ((base >> 16) & 0xFFFF) as u16;
+	fn set_gate(&mut self, n: usize, base: usize, sel: u16, flags: u8)
+	{
+		let entry = &mut self.entries[n];
+		entry.base_low = (base & 0xFFFF) as u16;
+		entry.base_high = ((base >> 16) & 0xFFFF) as u16;
+
+		entry.selector = sel;
+		entry.zero = 0;
+		entry.flags = flags;
+	}
 
-	idt_entries[n].selector = sel;
-	idt_entries[n].zero = 0;
-	idt_entries[n].flags = (flags & 0b11100000) | 0b01110;
+	/// Installs a task gate, so that the vector switches to the task in `tss_selector`
+	fn set_task_gate(&mut self, n: usize, tss_selector: u16)
+	{
+		let entry = &mut self.entries[n];
+		entry.base_low = 0;
+		entry.base_high = 0;
+
+		entry.selector = tss_selector;
+		entry.zero = 0;
+		entry.flags = 0x85;
+	}
 }
 
 extern
@@ -112,7 +141,6 @@ extern
 	fn isr5 ();
 	fn isr6 ();
 	fn isr7 ();
-	fn isr8 ();
 	fn isr9 ();
 	fn isr10();
 	fn isr11();
@@ -152,4 +180,6 @@ extern
 	fn irq13();
 	fn irq14();
 	fn irq15();
+	fn isr48();
+	fn isr128();
 }
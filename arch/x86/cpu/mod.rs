@@ -3,18 +3,52 @@ use core::clone::Clone;
 
 mod gdt;
 mod idt;
+mod tss;
 mod pic;
-mod timer;
+pub mod timer;
 mod features;
+pub mod context;
 
 static IRQ_OFFSET: u8 = 0x20;
+pub static YIELD_VECTOR: u32 = 0x30;
+pub static SYSCALL_VECTOR: u32 = 0x80;
 
+pub const KERNEL_CODE_SELECTOR: u32 = 0x08;
+pub const KERNEL_DATA_SELECTOR: u32 = 0x10;
+// GDT entries 3 and 4 with the requested privilege level set to 3
+pub const USER_CODE_SELECTOR: u32 = 0x1B;
+pub const USER_DATA_SELECTOR: u32 = 0x23;
+
+/// Register state saved by isr_common_stub, in the order it is pushed.
+/// `useresp` and `ss` are only valid when the interrupt came from ring 3.
 #[repr(C)]
 pub struct InterruptArguments {
-	_ds: u32, _edi: u32, _esi: u32, _ebp: u32, _esp: u32, _ebx: u32, _edx: u32, _ecx: u32, _eax: u32,
-	interrupt_number: u32,
-	error_code: u32,
-	_eip: u32, _cs: u32, _eflags: u32, _useresp: u32, _ss: u32,
+	pub ds: u32, pub edi: u32, pub esi: u32, pub ebp: u32, pub esp: u32, pub ebx: u32, pub edx: u32, pub ecx: u32, pub eax: u32,
+	pub interrupt_number: u32,
+	pub error_code: u32,
+	pub eip: u32, pub cs: u32, pub eflags: u32, pub useresp: u32, pub ss: u32,
+}
+
+impl InterruptArguments
+{
+	pub fn from_user_mode(&self) -> bool
+	{
+		self.cs & 0x3 == 3
+	}
+
+	/// Stack pointer of the interrupted code
+	pub fn stack_pointer(&self) -> u32
+	{
+		if self.from_user_mode()
+		{
+			self.useresp
+		}
+		else
+		{
+			// pushad saw the stack with the vector, error code, EIP, CS and EFLAGS on it
+			self.esp + 20
+		}
+	}
 }
 
 impl Copy for InterruptArguments {}
@@ -28,6 +62,27 @@ pub fn idle()
 	}
 }
 
+/// Hint for spin loops
+pub fn relax()
+{
+	unsafe
+	{
+		asm!("pause" :::: "volatile");
+	}
+}
+
+/// Cycles counted by the CPU since reset
+pub fn timestamp() -> u64
+{
+	let low: u32;
+	let high: u32;
+	unsafe
+	{
+		asm!("rdtsc" : "={eax}"(low), "={edx}"(high) ::: "volatile");
+	}
+	(high as u64) << 32 | low as u64
+}
+
 pub fn halt() -> !
 {
 	loop
@@ -40,13 +95,14 @@ pub fn halt() -> !
 	}
 }
 
+/// Raises a breakpoint exception. The timer keeps running, the scheduler
+/// depends on it.
 pub fn request_int3()
 {
 	unsafe
 	{
 		asm!("int $$0x03");
 	}
-	pic::disable_irq(0);
 }
 
 pub fn setup()
@@ -55,27 +111,103 @@ pub fn setup()
 	gdt::init_gdt();
 	pic::remap_pic(IRQ_OFFSET);
 	idt::init_idt();
-	timer::set_interval(50);
 }
 
+/// Returns the context to resume, which differs from `context` when the
+/// kernel decided to switch threads
 #[no_mangle]
-pub extern "C" fn isr_handler(args: &InterruptArguments, _fpu_sse_data: [u8; 512])
+pub extern "C" fn isr_handler(context: *mut context::Context) -> *mut context::Context
 {
-	::kernel::interrupts::handle_interrupt(args.interrupt_number, args.error_code);
+	let args = unsafe { &mut *(*context).args };
+	::kernel::interrupts::handle_interrupt(args);
 
 	// Ack IRQ
-	if args.interrupt_number >= (IRQ_OFFSET as u32)
+	if args.interrupt_number >= (IRQ_OFFSET as u32) && args.interrupt_number < (IRQ_OFFSET as u32) + 16
 	{
 		pic::acknowledge_irq(args.interrupt_number as u8 - IRQ_OFFSET);
 	}
+
+	::kernel::thread::switch_if_needed(context)
+}
+
+/// Enters isr_handler through YIELD_VECTOR so the current thread can be switched out
+pub fn yield_interrupt()
+{
+	unsafe
+	{
+		asm!("int $$0x30" :::: "volatile");
+	}
+}
+
+/// Sets the top of the stack the CPU switches to when ring 3 code is interrupted
+pub fn set_kernel_stack(stack_top: u32)
+{
+	tss::set_kernel_stack(stack_top);
+}
+
+pub fn set_fault_directory(directory: u32)
+{
+	tss::set_fault_directory(directory);
+}
+
+/// Runs as the double fault task. The state of the task that faulted was saved
+/// into the kernel TSS by the task switch.
+#[no_mangle]
+pub extern "C" fn double_fault_handler(error_code: u32) -> !
+{
+	let saved = unsafe { tss::kernel_tss };
+	let args = InterruptArguments {
+		ds: saved.ds, edi: saved.edi, esi: saved.esi, ebp: saved.ebp,
+		// stack_pointer() adds back what an interrupt stub would have pushed
+		esp: saved.esp - 20,
+		ebx: saved.ebx, edx: saved.edx, ecx: saved.ecx, eax: saved.eax,
+		interrupt_number: 8,
+		error_code: error_code,
+		eip: saved.eip, cs: saved.cs, eflags: saved.eflags, useresp: saved.esp, ss: saved.ss,
+	};
+	::kernel::interrupts::handle_double_fault(&args);
+}
+
+pub fn enable_irq(irq: u32)
+{
+	pic::enable_irq(irq);
+}
+
+pub fn disable_irq(irq: u32)
+{
+	pic::disable_irq(irq);
 }
 
 pub fn enable_interrupts()
 {
-	pic::enable_irq(0);
-	pic::enable_irq(1);
 	unsafe
 	{
 		asm!("sti");
 	}
 }
+
+/// Disables interrupts and returns the previous EFLAGS for `restore_interrupts`
+pub fn disable_interrupts() -> u32
+{
+	let flags;
+	unsafe
+	{
+		asm!("pushfl
+		      pop $0
+		      cli"
+		      : "=r"(flags)
+		      :
+		      : "memory"
+		      : "volatile");
+	}
+	flags
+}
+
+/// Turns interrupts back on if they were on when `disable_interrupts` returned `flags`
+pub fn restore_interrupts(flags: u32)
+{
+	if flags & 0x200 != 0
+	{
+		enable_interrupts();
+	}
+}
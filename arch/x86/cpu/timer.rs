@@ -1,17 +1,51 @@
+use core::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
 use platform::io;
 
 static TIMER_COMMAND: u16 = 0x43;
 static TIMER_CHANNEL0: u16 = 0x40;
 
+pub static BASE_FREQUENCY: u32 = 1193182;
+
+static CURRENT_FREQUENCY: AtomicUsize = ATOMIC_USIZE_INIT;
+static CURRENT_DIVISOR: AtomicUsize = ATOMIC_USIZE_INIT;
+
 pub fn set_interval(frequency: u32)
 {
-	let divisor = 1193180 / frequency;
+	let divisor = BASE_FREQUENCY / frequency;
 	let l = divisor as u8;
 	let h = (divisor >> 8) as u8;
+	CURRENT_FREQUENCY.store(frequency as usize, Ordering::SeqCst);
+	CURRENT_DIVISOR.store(divisor as usize, Ordering::SeqCst);
 	unsafe
 	{
-		io::outport(TIMER_COMMAND, 0x36); // 0x36 tells timer to repeat
+		// Mode 2, the rate generator: repeats and counts down by one per input
+		// clock, which delay_us relies on. Mode 3 would count down by two.
+		io::outport(TIMER_COMMAND, 0x34);
 		io::outport(TIMER_CHANNEL0, l);
 		io::outport(TIMER_CHANNEL0, h);
 	}
 }
+
+/// Interrupt frequency set with `set_interval`
+pub fn frequency() -> u32
+{
+	CURRENT_FREQUENCY.load(Ordering::SeqCst) as u32
+}
+
+/// Number of PIT input clocks between two interrupts
+pub fn divisor() -> u32
+{
+	CURRENT_DIVISOR.load(Ordering::SeqCst) as u32
+}
+
+/// Latches and reads the current value of channel 0, which counts down to 0
+pub fn read_counter() -> u32
+{
+	unsafe
+	{
+		io::outport(TIMER_COMMAND, 0x00);
+		let l = io::inport(TIMER_CHANNEL0) as u32;
+		let h = io::inport(TIMER_CHANNEL0) as u32;
+		(h << 8) | l
+	}
+}
@@ -0,0 +1,105 @@
+/*
+ * Task State Segments
+ *
+ * The kernel TSS is loaded into TR at boot. Its esp0 is the stack the CPU
+ * switches to when an interrupt arrives in ring 3, so it always points at the
+ * top of the running thread's kernel stack.
+ *
+ * The CPU also saves the interrupted state into the kernel TSS when it switches
+ * to the fault task, which runs the double fault handler on its own stack so
+ * that a kernel stack overflow can still be reported.
+ */
+
+use core::marker::Copy;
+use core::clone::Clone;
+
+const FAULT_STACK_SIZE: usize = 8192;
+
+#[repr(C, packed)]
+pub struct TaskStateSegment
+{
+	pub prev_task: u32,
+	pub esp0: u32,
+	pub ss0: u32,
+	pub esp1: u32,
+	pub ss1: u32,
+	pub esp2: u32,
+	pub ss2: u32,
+	pub cr3: u32,
+	pub eip: u32,
+	pub eflags: u32,
+	pub eax: u32,
+	pub ecx: u32,
+	pub edx: u32,
+	pub ebx: u32,
+	pub esp: u32,
+	pub ebp: u32,
+	pub esi: u32,
+	pub edi: u32,
+	pub es: u32,
+	pub cs: u32,
+	pub ss: u32,
+	pub ds: u32,
+	pub fs: u32,
+	pub gs: u32,
+	pub ldt: u32,
+	pub trap: u16,
+	pub iomap_base: u16
+}
+
+impl Copy for TaskStateSegment {}
+impl Clone for TaskStateSegment { fn clone(&self) -> Self { *self } }
+
+const EMPTY_TSS: TaskStateSegment = TaskStateSegment {
+	prev_task: 0, esp0: 0, ss0: 0, esp1: 0, ss1: 0, esp2: 0, ss2: 0, cr3: 0, eip: 0, eflags: 0,
+	eax: 0, ecx: 0, edx: 0, ebx: 0, esp: 0, ebp: 0, esi: 0, edi: 0,
+	es: 0, cs: 0, ss: 0, ds: 0, fs: 0, gs: 0, ldt: 0, trap: 0, iomap_base: 0
+};
+
+pub static mut kernel_tss: TaskStateSegment = EMPTY_TSS;
+pub static mut fault_tss: TaskStateSegment = EMPTY_TSS;
+static mut fault_stack: [u8; FAULT_STACK_SIZE] = [0; FAULT_STACK_SIZE];
+
+pub fn size() -> usize
+{
+	::core::mem::size_of::<TaskStateSegment>()
+}
+
+pub fn init_tss()
+{
+	unsafe
+	{
+		kernel_tss.ss0 = 0x10;
+		kernel_tss.iomap_base = size() as u16;
+
+		fault_tss.eip = double_fault_entry as u32;
+		fault_tss.esp = &fault_stack as *const [u8; FAULT_STACK_SIZE] as u32 + FAULT_STACK_SIZE as u32;
+		fault_tss.ss0 = 0x10;
+		fault_tss.cs = 0x08;
+		fault_tss.ds = 0x10;
+		fault_tss.es = 0x10;
+		fault_tss.fs = 0x10;
+		fault_tss.gs = 0x10;
+		fault_tss.ss = 0x10;
+		// Interrupts stay off while the fault task runs
+		fault_tss.eflags = 0x2;
+		fault_tss.iomap_base = size() as u16;
+	}
+}
+
+/// Sets the stack used for interrupts that arrive while ring 3 code runs
+pub fn set_kernel_stack(esp0: u32)
+{
+	unsafe { kernel_tss.esp0 = esp0; }
+}
+
+/// The page directory the fault task runs with
+pub fn set_fault_directory(directory: u32)
+{
+	unsafe { fault_tss.cr3 = directory; }
+}
+
+extern
+{
+	fn double_fault_entry();
+}
@@ -0,0 +1,249 @@
+/*
+ * Physical page frame allocator
+ *
+ * Every 4KiB frame below 4GiB gets one bit in a bitmap. A set bit means the
+ * frame is free, so the bitmap can live in .bss and starts out with every
+ * frame unavailable until the memory map tells us otherwise.
+ *
+ * Frames shared between address spaces get a reference count, kept in a map
+ * on the kernel heap. Frames missing from that map have a single owner.
+ *
+ * Frames can be promised ahead of time with `reserve_frames`. They stay free,
+ * but `alloc_frame` leaves them for `alloc_reserved_frame`, so that the kernel
+ * heap can back its pages lazily without running out of frames halfway.
+ */
+
+use core::prelude::*;
+use collections::BTreeMap;
+use kernel::sync::{IrqLock, Once};
+
+pub const FRAME_SIZE: u32 = 4096;
+
+const FRAME_COUNT: usize = 1024 * 1024;
+const BITMAP_WORDS: usize = FRAME_COUNT / 32;
+
+struct FrameAllocator
+{
+	bitmap: [u32; BITMAP_WORDS],
+	total_frames: u32,
+	free_frames: u32,
+	// Free frames promised by `reserve_frames`
+	reserved_frames: u32,
+	next_free: usize
+}
+
+static FRAMES: IrqLock<FrameAllocator> = irq_lock!(FrameAllocator { bitmap: [0; BITMAP_WORDS], total_frames: 0, free_frames: 0, reserved_frames: 0, next_free: 0 });
+
+// Frame number to the number of references beyond the first
+static SHARED: Once<IrqLock<BTreeMap<u32, u32>>> = once!();
+
+/// Marks the frames fully contained in [start, start + length) as available
+pub fn add_region(start: u64, length: u64)
+{
+	let limit = (FRAME_COUNT as u64) * (FRAME_SIZE as u64);
+	if start >= limit { return }
+
+	let stop = if start + length > limit { limit } else { start + length };
+	let first = (start + FRAME_SIZE as u64 - 1) / FRAME_SIZE as u64;
+	let last = stop / FRAME_SIZE as u64;
+
+	let mut frames = FRAMES.lock();
+	for frame in (first as usize .. last as usize)
+	{
+		if !frames.is_free(frame)
+		{
+			frames.set_free(frame);
+			frames.total_frames += 1;
+			frames.free_frames += 1;
+		}
+	}
+}
+
+/// Takes every frame touching [start, end) out of the free pool
+pub fn reserve_region(start: u32, end: u32)
+{
+	if end <= start { return }
+
+	let first = (start / FRAME_SIZE) as usize;
+	let last = ((end as u64 + FRAME_SIZE as u64 - 1) / FRAME_SIZE as u64) as usize;
+
+	let mut frames = FRAMES.lock();
+	for frame in (first .. last)
+	{
+		if frames.is_free(frame)
+		{
+			frames.set_used(frame);
+			frames.free_frames -= 1;
+		}
+	}
+}
+
+/// Returns the physical address of a free frame that is not reserved
+pub fn alloc_frame() -> Option<u32>
+{
+	let mut frames = FRAMES.lock();
+	if frames.free_frames <= frames.reserved_frames { return None }
+	frames.take_frame()
+}
+
+/// Promises `count` frames to a later `alloc_reserved_frame` each. Returns
+/// false when fewer frames are left.
+pub fn reserve_frames(count: u32) -> bool
+{
+	let mut frames = FRAMES.lock();
+	if frames.free_frames - frames.reserved_frames < count { return false }
+	frames.reserved_frames += count;
+	true
+}
+
+/// Allocates a frame promised by `reserve_frames`
+pub fn alloc_reserved_frame() -> Option<u32>
+{
+	let mut frames = FRAMES.lock();
+	if frames.reserved_frames == 0 { return None }
+	frames.reserved_frames -= 1;
+	frames.take_frame()
+}
+
+/// Returns the physical address of `count` free frames that follow each other
+pub fn alloc_contiguous(count: u32) -> Option<u32>
+{
+	if count == 0 { return None }
+
+	let mut frames = FRAMES.lock();
+	if frames.free_frames - frames.reserved_frames < count { return None }
+
+	let count = count as usize;
+	let mut run_start = 0;
+	let mut run_length = 0;
+
+	for frame in (0 .. FRAME_COUNT)
+	{
+		if frames.is_free(frame)
+		{
+			if run_length == 0 { run_start = frame; }
+			run_length += 1;
+			if run_length == count
+			{
+				for f in (run_start .. run_start + count)
+				{
+					frames.set_used(f);
+				}
+				frames.free_frames -= count as u32;
+				return Some(run_start as u32 * FRAME_SIZE);
+			}
+		}
+		else
+		{
+			run_length = 0;
+		}
+	}
+	None
+}
+
+/// Records one more owner of an allocated frame, who has to call `free_frame`
+/// for it as well
+pub fn add_reference(address: u32)
+{
+	let frame = address / FRAME_SIZE;
+	let mut shared = SHARED.call_once(|| IrqLock::new(BTreeMap::new())).lock();
+	let extra = match shared.get(&frame) { Some(&extra) => extra + 1, None => 1 };
+	shared.insert(frame, extra);
+}
+
+/// Number of owners of an allocated frame
+pub fn reference_count(address: u32) -> u32
+{
+	let frame = address / FRAME_SIZE;
+	match SHARED.get()
+	{
+		Some(shared) => match shared.lock().get(&frame) { Some(&extra) => extra + 1, None => 1 },
+		None => 1,
+	}
+}
+
+/// Returns a frame obtained from `alloc_frame` or `alloc_contiguous` to the
+/// pool once its last owner frees it
+pub fn free_frame(address: u32)
+{
+	if let Some(shared) = SHARED.get()
+	{
+		let mut shared = shared.lock();
+		let key = address / FRAME_SIZE;
+		let extra = match shared.get(&key) { Some(&extra) => extra, None => 0 };
+		if extra > 1
+		{
+			shared.insert(key, extra - 1);
+			return;
+		}
+		if extra == 1
+		{
+			shared.remove(&key);
+			return;
+		}
+	}
+
+	let frame = (address / FRAME_SIZE) as usize;
+	let mut frames = FRAMES.lock();
+	if frames.is_free(frame)
+	{
+		panic!("Double free of frame {:x}", address);
+	}
+	frames.set_free(frame);
+	frames.free_frames += 1;
+	if frame < frames.next_free { frames.next_free = frame; }
+}
+
+/// Free frames that are not reserved
+pub fn free_count() -> u32
+{
+	let frames = FRAMES.lock();
+	frames.free_frames - frames.reserved_frames
+}
+
+pub fn used_count() -> u32
+{
+	let frames = FRAMES.lock();
+	frames.total_frames - frames.free_frames
+}
+
+pub fn total_count() -> u32
+{
+	FRAMES.lock().total_frames
+}
+
+impl FrameAllocator
+{
+	fn take_frame(&mut self) -> Option<u32>
+	{
+		let mut word = self.next_free / 32;
+		while word < BITMAP_WORDS
+		{
+			if self.bitmap[word] != 0
+			{
+				let frame = word * 32 + self.bitmap[word].trailing_zeros() as usize;
+				self.set_used(frame);
+				self.free_frames -= 1;
+				self.next_free = frame + 1;
+				return Some(frame as u32 * FRAME_SIZE);
+			}
+			word += 1;
+		}
+		None
+	}
+
+	fn is_free(&self, frame: usize) -> bool
+	{
+		self.bitmap[frame / 32] & (1 << (frame % 32)) != 0
+	}
+
+	fn set_free(&mut self, frame: usize)
+	{
+		self.bitmap[frame / 32] |= 1 << (frame % 32);
+	}
+
+	fn set_used(&mut self, frame: usize)
+	{
+		self.bitmap[frame / 32] &= !(1 << (frame % 32));
+	}
+}
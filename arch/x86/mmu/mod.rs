@@ -1,14 +1,59 @@
+use core::prelude::*;
+use core::cmp;
+use kernel::boot::{BootInfo, MemoryKind};
+
+pub mod frame;
+pub mod paging;
+
 extern
 {
 	static end: u32;
+	static stack_guard: u8;
 }
 
-static mut placement_address: u32 = 0;
+const KERNEL_START: u32 = 0x100000;
 
-pub fn setup()
+pub fn setup(boot: &BootInfo)
 {
-	unsafe
+	for region in boot.memory_regions().iter()
+	{
+		if let MemoryKind::Available = region.kind
+		{
+			frame::add_region(region.start, region.length);
+		}
+	}
+
+	// The first megabyte holds the BIOS data, the VGA hole and our multiboot data
+	frame::reserve_region(0, KERNEL_START);
+	frame::reserve_region(KERNEL_START, kernel_end());
+	for module in boot.modules().iter()
+	{
+		frame::reserve_region(module.start, module.end);
+	}
+	// The section headers are left in the boot loader's memory
+	let sections = boot.elf_sections.map(|s| (s.address, s.address.saturating_add(s.count.saturating_mul(s.entry_size))));
+	if let Some((start, end)) = sections
 	{
-		placement_address = end;
+		frame::reserve_region(start, end);
 	}
+
+	// Everything we booted with stays reachable at its physical address.
+	// paging::init maps everything below the kernel end writable already, and
+	// mapping those pages again read-only would break the kernel.
+	paging::init(kernel_end());
+	for module in boot.modules().iter()
+	{
+		paging::identity_map(cmp::max(module.start, kernel_end()), module.end, 0);
+	}
+	if let Some((start, end)) = sections
+	{
+		paging::identity_map(cmp::max(start, kernel_end()), end, 0);
+	}
+	unsafe { paging::unmap(&stack_guard as *const u8 as u32); }
+	paging::enable();
+}
+
+pub fn kernel_end() -> u32
+{
+	unsafe { &end as *const u32 as u32 }
 }
@@ -0,0 +1,463 @@
+/*
+ * Two-level x86 paging
+ *
+ * The last entry of every page directory points back at the directory itself.
+ * Once paging is on, this makes the directory visible at 0xFFFFF000 and the
+ * page table for directory entry n at 0xFFC00000 + n * 0x1000.
+ *
+ * Every address space has its own mappings between USER_START and USER_END.
+ * The directory entries outside that range are copied from the kernel
+ * directory when the address space is created, so the kernel tables have to
+ * exist by then, see `reserve_tables`.
+ *
+ * Directory entry SCRATCH_SLOT is left empty. Pointing it at any frame makes
+ * that frame visible at SCRATCH_PAGE, which is how directories and tables of
+ * other address spaces are edited.
+ */
+
+use core::prelude::*;
+use collections::vec::Vec;
+use platform::mmu::frame;
+use kernel::sync::IrqLock;
+
+pub const PRESENT: u32 = 1 << 0;
+pub const WRITABLE: u32 = 1 << 1;
+pub const USER: u32 = 1 << 2;
+pub const WRITE_THROUGH: u32 = 1 << 3;
+pub const CACHE_DISABLE: u32 = 1 << 4;
+pub const ACCESSED: u32 = 1 << 5;
+pub const DIRTY: u32 = 1 << 6;
+pub const GLOBAL: u32 = 1 << 8;
+/// Available to the OS: the page is read-only because its frame is shared
+/// after a fork, and gets copied on the first write
+pub const COPY_ON_WRITE: u32 = 1 << 9;
+
+pub const PAGE_SIZE: u32 = 4096;
+
+pub const USER_START: u32 = 0x40000000;
+pub const USER_END: u32 = 0xC0000000;
+
+const ENTRY_COUNT: usize = 1024;
+const RECURSIVE_SLOT: usize = 1023;
+const RECURSIVE_DIRECTORY: u32 = 0xFFFFF000;
+const RECURSIVE_TABLES: u32 = 0xFFC00000;
+const SCRATCH_SLOT: usize = 1022;
+const SCRATCH_PAGE: u32 = 0xFFFFE000;
+const ADDRESS_MASK: u32 = 0xFFFFF000;
+const FLAGS_MASK: u32 = 0x00000FFF;
+
+const USER_FIRST_SLOT: usize = (USER_START >> 22) as usize;
+const USER_END_SLOT: usize = (USER_END >> 22) as usize;
+
+static SCRATCH: IrqLock<()> = irq_lock!(());
+
+static mut kernel_directory: u32 = 0;
+static mut paging_enabled: bool = false;
+
+/// Builds the kernel page directory and identity maps [0, identity_end)
+pub fn init(identity_end: u32)
+{
+	unsafe
+	{
+		kernel_directory = new_table();
+		let directory = kernel_directory as *mut u32;
+		*directory.offset(RECURSIVE_SLOT as isize) = kernel_directory | PRESENT | WRITABLE;
+	}
+
+	// Page 0 has to stay mapped: the stack check in every function prologue
+	// reads the stack limit at %gs:0x30, and gs is flat (see runtime.asm)
+	identity_map(0, identity_end, WRITABLE);
+}
+
+/// Maps [start, end) to the same physical addresses
+pub fn identity_map(start: u32, end: u32, flags: u32)
+{
+	let mut page = start & ADDRESS_MASK;
+	while page < end
+	{
+		map(page, page, flags);
+		page = match page.checked_add(PAGE_SIZE) { Some(p) => p, None => break };
+	}
+}
+
+/// Loads the kernel page directory and sets CR0.PG. CR0.WP is set as well, so
+/// that kernel writes to read-only user pages fault like user writes do.
+pub fn enable()
+{
+	unsafe
+	{
+		load_directory(kernel_directory);
+		asm!("mov %cr0, %eax
+		      or $$0x80010000, %eax
+		      mov %eax, %cr0"
+		      :
+		      :
+		      : "eax"
+		      : "volatile");
+		paging_enabled = true;
+	}
+	::platform::cpu::set_fault_directory(kernel_directory_address());
+}
+
+pub fn kernel_directory_address() -> u32
+{
+	unsafe { kernel_directory }
+}
+
+/// Maps the page containing `virt` to the frame containing `phys`
+pub fn map(virt: u32, phys: u32, flags: u32)
+{
+	let (dir_index, table_index) = indices(virt);
+	unsafe
+	{
+		let directory = directory_ptr();
+		let pde = *directory.offset(dir_index as isize);
+		if pde & PRESENT == 0
+		{
+			let table = new_table();
+			*directory.offset(dir_index as isize) = table | PRESENT | WRITABLE | (flags & USER);
+			if paging_enabled
+			{
+				invalidate(table_ptr(dir_index) as u32);
+				zero_page(table_ptr(dir_index));
+			}
+		}
+		else if flags & USER != 0 && pde & USER == 0
+		{
+			*directory.offset(dir_index as isize) = pde | USER;
+		}
+
+		let table = table_ptr(dir_index);
+		*table.offset(table_index as isize) = (phys & ADDRESS_MASK) | (flags & FLAGS_MASK) | PRESENT;
+		invalidate(virt);
+	}
+}
+
+/// Removes the mapping of the page containing `virt` and returns the frame it used
+pub fn unmap(virt: u32) -> Option<u32>
+{
+	let (dir_index, table_index) = indices(virt);
+	unsafe
+	{
+		if *directory_ptr().offset(dir_index as isize) & PRESENT == 0 { return None }
+
+		let entry = table_ptr(dir_index).offset(table_index as isize);
+		if *entry & PRESENT == 0 { return None }
+
+		let phys = *entry & ADDRESS_MASK;
+		*entry = 0;
+		invalidate(virt);
+		Some(phys)
+	}
+}
+
+/// Returns the physical address `virt` is mapped to
+pub fn translate(virt: u32) -> Option<u32>
+{
+	match page_entry(virt)
+	{
+		Some(entry) => Some((entry & ADDRESS_MASK) | (virt & FLAGS_MASK)),
+		None => None,
+	}
+}
+
+/// Returns the raw page table entry for `virt` if it is present
+pub fn page_entry(virt: u32) -> Option<u32>
+{
+	let (dir_index, table_index) = indices(virt);
+	unsafe
+	{
+		if *directory_ptr().offset(dir_index as isize) & PRESENT == 0 { return None }
+
+		let entry = *table_ptr(dir_index).offset(table_index as isize);
+		if entry & PRESENT == 0 { None } else { Some(entry) }
+	}
+}
+
+/// Creates the page tables for [start, end) up front, so that address spaces
+/// created later share them with the kernel
+pub fn reserve_tables(start: u32, end: u32)
+{
+	let mut dir_index = (start >> 22) as usize;
+	while dir_index < ENTRY_COUNT && (dir_index as u32) << 22 < end
+	{
+		unsafe
+		{
+			let directory = directory_ptr();
+			if *directory.offset(dir_index as isize) & PRESENT == 0
+			{
+				*directory.offset(dir_index as isize) = new_table() | PRESENT | WRITABLE;
+				if paging_enabled
+				{
+					invalidate(table_ptr(dir_index) as u32);
+					zero_page(table_ptr(dir_index));
+				}
+			}
+		}
+		dir_index += 1;
+	}
+}
+
+/// Builds a page directory with an empty user range that shares the kernel
+/// mappings of the current one. Returns its physical address.
+pub fn create_address_space() -> Option<u32>
+{
+	let directory = match frame::alloc_frame() { Some(f) => f, None => return None };
+
+	let _scratch = SCRATCH.lock();
+	unsafe
+	{
+		let current = directory_ptr();
+		let new = view_frame(directory);
+		for i in (0 .. ENTRY_COUNT)
+		{
+			*new.offset(i as isize) = if is_user_slot(i) || i == SCRATCH_SLOT { 0 } else { *current.offset(i as isize) };
+		}
+		*new.offset(RECURSIVE_SLOT as isize) = directory | PRESENT | WRITABLE;
+		release_view();
+	}
+	Some(directory)
+}
+
+/// Frees the user pages, the user page tables and the directory of an address
+/// space from `create_address_space`. It must not be loaded.
+pub fn destroy_address_space(directory: u32)
+{
+	if directory == current_directory() || directory == kernel_directory_address()
+	{
+		panic!("Destroying an address space that is in use");
+	}
+
+	let _scratch = SCRATCH.lock();
+	// Only one frame can be viewed at a time, so the user entries are copied out
+	let mut tables = Vec::with_capacity(USER_END_SLOT - USER_FIRST_SLOT);
+	unsafe
+	{
+		let view = view_frame(directory);
+		for i in (USER_FIRST_SLOT .. USER_END_SLOT)
+		{
+			tables.push(*view.offset(i as isize));
+		}
+
+		for &table in tables.iter()
+		{
+			if table & PRESENT == 0 { continue }
+			let view = view_frame(table & ADDRESS_MASK);
+			for i in (0 .. ENTRY_COUNT)
+			{
+				let entry = *view.offset(i as isize);
+				if entry & PRESENT != 0
+				{
+					frame::free_frame(entry & ADDRESS_MASK);
+				}
+			}
+			frame::free_frame(table & ADDRESS_MASK);
+		}
+		release_view();
+	}
+	frame::free_frame(directory);
+}
+
+/// Creates an address space whose user half shares every frame with the loaded
+/// one. Writable pages turn read-only and COPY_ON_WRITE in both, so whichever
+/// side writes first gets its own copy, see `resolve_copy_on_write`.
+pub fn clone_address_space() -> Option<u32>
+{
+	let directory = match create_address_space() { Some(d) => d, None => return None };
+	if unsafe { copy_user_tables(directory) }
+	{
+		Some(directory)
+	}
+	else
+	{
+		destroy_address_space(directory);
+		None
+	}
+}
+
+unsafe fn copy_user_tables(directory: u32) -> bool
+{
+	let _scratch = SCRATCH.lock();
+	let current = directory_ptr();
+	let mut complete = true;
+	for dir_index in (USER_FIRST_SLOT .. USER_END_SLOT)
+	{
+		let pde = *current.offset(dir_index as isize);
+		if pde & PRESENT == 0 { continue }
+
+		let table = match frame::alloc_frame() { Some(f) => f, None => { complete = false; break } };
+		let source = table_ptr(dir_index);
+		let copy = view_frame(table);
+		for i in (0 .. ENTRY_COUNT as isize)
+		{
+			let mut entry = *source.offset(i);
+			if entry & PRESENT != 0
+			{
+				if entry & WRITABLE != 0
+				{
+					entry = (entry & !WRITABLE) | COPY_ON_WRITE;
+					*source.offset(i) = entry;
+				}
+				frame::add_reference(entry & ADDRESS_MASK);
+			}
+			*copy.offset(i) = entry;
+		}
+		*view_frame(directory).offset(dir_index as isize) = table | (pde & FLAGS_MASK);
+	}
+	release_view();
+
+	// Drops the stale writable translations of the pages that became shared
+	load_directory(current_directory());
+	complete
+}
+
+/// Gives the loaded address space a private, writable copy of the
+/// COPY_ON_WRITE page at `address`. Returns false if it is no such page.
+pub fn resolve_copy_on_write(address: u32) -> bool
+{
+	let page = address & ADDRESS_MASK;
+	let entry = match page_entry(page) { Some(entry) => entry, None => return false };
+	if entry & COPY_ON_WRITE == 0 { return false }
+
+	let shared = entry & ADDRESS_MASK;
+	let flags = (entry & FLAGS_MASK & !COPY_ON_WRITE) | WRITABLE;
+	if frame::reference_count(shared) == 1
+	{
+		// The other owners are gone, so the frame is ours alone
+		map(page, shared, flags);
+		return true;
+	}
+
+	let private = match frame::alloc_frame() { Some(f) => f, None => return false };
+	{
+		let _scratch = SCRATCH.lock();
+		unsafe
+		{
+			let copy = view_frame(private);
+			for i in (0 .. ENTRY_COUNT as isize)
+			{
+				*copy.offset(i) = *(page as *const u32).offset(i);
+			}
+			release_view();
+		}
+	}
+	map(page, private, flags);
+	frame::free_frame(shared);
+	true
+}
+
+/// Physical address of the loaded page directory
+pub fn current_directory() -> u32
+{
+	let directory: u32;
+	unsafe
+	{
+		asm!("mov %cr3, $0" : "=r"(directory) ::: "volatile");
+	}
+	directory
+}
+
+/// Loads `directory` into CR3 unless it already is
+pub fn switch_directory(directory: u32)
+{
+	if directory != current_directory()
+	{
+		unsafe { load_directory(directory); }
+	}
+}
+
+pub fn is_user_address(address: u32) -> bool
+{
+	address >= USER_START && address < USER_END
+}
+
+/// Returns the linear address that caused the last page fault
+pub fn fault_address() -> u32
+{
+	let address;
+	unsafe
+	{
+		asm!("mov %cr2, $0" : "=r"(address) ::: "volatile");
+	}
+	address
+}
+
+fn is_user_slot(dir_index: usize) -> bool
+{
+	dir_index >= USER_FIRST_SLOT && dir_index < USER_END_SLOT
+}
+
+/// Makes `frame` visible at SCRATCH_PAGE. The SCRATCH lock must be held.
+unsafe fn view_frame(frame: u32) -> *mut u32
+{
+	*directory_ptr().offset(SCRATCH_SLOT as isize) = frame | PRESENT | WRITABLE;
+	invalidate(SCRATCH_PAGE);
+	SCRATCH_PAGE as *mut u32
+}
+
+unsafe fn release_view()
+{
+	*directory_ptr().offset(SCRATCH_SLOT as isize) = 0;
+	invalidate(SCRATCH_PAGE);
+}
+
+fn indices(virt: u32) -> (usize, usize)
+{
+	((virt >> 22) as usize, ((virt >> 12) & 0x3FF) as usize)
+}
+
+unsafe fn directory_ptr() -> *mut u32
+{
+	if paging_enabled { RECURSIVE_DIRECTORY as *mut u32 } else { kernel_directory as *mut u32 }
+}
+
+unsafe fn table_ptr(dir_index: usize) -> *mut u32
+{
+	if paging_enabled
+	{
+		(RECURSIVE_TABLES + dir_index as u32 * PAGE_SIZE) as *mut u32
+	}
+	else
+	{
+		(*directory_ptr().offset(dir_index as isize) & ADDRESS_MASK) as *mut u32
+	}
+}
+
+/// Allocates a frame for a page table. Before paging is on it is zeroed in place.
+unsafe fn new_table() -> u32
+{
+	let table = match frame::alloc_frame()
+	{
+		Some(f) => f,
+		None => panic!("Out of physical memory for page tables"),
+	};
+	if !paging_enabled
+	{
+		zero_page(table as *mut u32);
+	}
+	table
+}
+
+unsafe fn zero_page(page: *mut u32)
+{
+	for i in (0 .. ENTRY_COUNT)
+	{
+		*page.offset(i as isize) = 0;
+	}
+}
+
+unsafe fn load_directory(directory: u32)
+{
+	asm!("mov $0, %cr3" :: "r"(directory) :: "volatile");
+}
+
+fn invalidate(virt: u32)
+{
+	unsafe
+	{
+		if paging_enabled
+		{
+			asm!("invlpg ($0)" :: "r"(virt) : "memory" : "volatile");
+		}
+	}
+}
@@ -0,0 +1,166 @@
+/*
+ * Multiboot (version 1) information structure
+ *
+ * See: https://www.gnu.org/software/grub/manual/multiboot/multiboot.html
+ *
+ * Runs before paging is enabled, while every physical address is reachable.
+ */
+
+use core::prelude::*;
+use kernel::boot::{BootInfo, BootString, MemoryRegion, MemoryKind, Module, Framebuffer, ElfSections, Protocol};
+
+/// Value the boot loader leaves in EAX
+pub const BOOTLOADER_MAGIC: u32 = 0x2BADB002;
+
+const FLAG_MEMORY: u32 = 1 << 0;
+const FLAG_CMDLINE: u32 = 1 << 2;
+const FLAG_MODULES: u32 = 1 << 3;
+const FLAG_ELF_SECTIONS: u32 = 1 << 5;
+const FLAG_MMAP: u32 = 1 << 6;
+const FLAG_LOADER_NAME: u32 = 1 << 9;
+const FLAG_FRAMEBUFFER: u32 = 1 << 12;
+
+const UPPER_MEMORY_START: u64 = 0x100000;
+
+#[repr(C, packed)]
+struct MultibootInfo
+{
+	flags: u32,
+	mem_lower: u32,
+	mem_upper: u32,
+	boot_device: u32,
+	cmdline: u32,
+	mods_count: u32,
+	mods_addr: u32,
+	syms: [u32; 4],
+	mmap_length: u32,
+	mmap_addr: u32,
+	drives_length: u32,
+	drives_addr: u32,
+	config_table: u32,
+	boot_loader_name: u32,
+	apm_table: u32,
+	vbe_control_info: u32,
+	vbe_mode_info: u32,
+	vbe_mode: u16,
+	vbe_interface_seg: u16,
+	vbe_interface_off: u16,
+	vbe_interface_len: u16,
+	framebuffer_addr: u64,
+	framebuffer_pitch: u32,
+	framebuffer_width: u32,
+	framebuffer_height: u32,
+	framebuffer_bpp: u8,
+	framebuffer_type: u8
+}
+
+#[repr(C, packed)]
+struct MmapEntry
+{
+	size: u32,
+	base_addr: u64,
+	length: u64,
+	kind: u32
+}
+
+#[repr(C, packed)]
+struct ModuleEntry
+{
+	mod_start: u32,
+	mod_end: u32,
+	string: u32,
+	reserved: u32
+}
+
+/// Parses the information structure at `address`. Returns None unless
+/// `magic` shows that a multiboot loader started us.
+pub fn parse(magic: u32, address: u32) -> Option<BootInfo>
+{
+	if magic != BOOTLOADER_MAGIC { return None }
+
+	let mut boot = BootInfo::new(Protocol::Multiboot);
+	unsafe
+	{
+		let info = &*(address as *const MultibootInfo);
+		parse_memory(info, &mut boot);
+
+		if info.flags & FLAG_CMDLINE != 0
+		{
+			boot.cmdline = BootString::from_c_string(info.cmdline);
+		}
+		if info.flags & FLAG_LOADER_NAME != 0
+		{
+			boot.bootloader_name = BootString::from_c_string(info.boot_loader_name);
+		}
+		if info.flags & FLAG_MODULES != 0
+		{
+			for i in (0 .. info.mods_count)
+			{
+				let module = &*((info.mods_addr + i * 16) as *const ModuleEntry);
+				boot.add_module(Module
+				{
+					start: module.mod_start,
+					end: module.mod_end,
+					cmdline: BootString::from_c_string(module.string)
+				});
+			}
+		}
+		if info.flags & FLAG_ELF_SECTIONS != 0
+		{
+			boot.elf_sections = Some(ElfSections
+			{
+				count: info.syms[0],
+				entry_size: info.syms[1],
+				address: info.syms[2],
+				string_index: info.syms[3]
+			});
+		}
+		if info.flags & FLAG_FRAMEBUFFER != 0
+		{
+			boot.framebuffer = Some(Framebuffer
+			{
+				address: info.framebuffer_addr,
+				pitch: info.framebuffer_pitch,
+				width: info.framebuffer_width,
+				height: info.framebuffer_height,
+				bits_per_pixel: info.framebuffer_bpp,
+				kind: info.framebuffer_type
+			});
+		}
+	}
+	Some(boot)
+}
+
+unsafe fn parse_memory(info: &MultibootInfo, boot: &mut BootInfo)
+{
+	if info.flags & FLAG_MMAP != 0
+	{
+		let mut entry = info.mmap_addr;
+		while entry < info.mmap_addr + info.mmap_length
+		{
+			let mmap = &*(entry as *const MmapEntry);
+			boot.add_memory_region(MemoryRegion { start: mmap.base_addr, length: mmap.length, kind: memory_kind(mmap.kind) });
+			// The size field does not count itself
+			entry += mmap.size + 4;
+		}
+	}
+	else if info.flags & FLAG_MEMORY != 0
+	{
+		// Without a map there are only the sizes of the memory below 1MiB and above it
+		boot.add_memory_region(MemoryRegion { start: 0, length: info.mem_lower as u64 * 1024, kind: MemoryKind::Available });
+		boot.add_memory_region(MemoryRegion { start: UPPER_MEMORY_START, length: info.mem_upper as u64 * 1024, kind: MemoryKind::Available });
+	}
+}
+
+/// Memory map types are the same in both protocol versions
+pub fn memory_kind(kind: u32) -> MemoryKind
+{
+	match kind
+	{
+		1 => MemoryKind::Available,
+		3 => MemoryKind::AcpiReclaimable,
+		4 => MemoryKind::AcpiNvs,
+		5 => MemoryKind::Defective,
+		_ => MemoryKind::Reserved,
+	}
+}
@@ -0,0 +1,227 @@
+/*
+ * Multiboot2 boot information
+ *
+ * See: https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html
+ *
+ * The information is a list of tags following an 8 byte header. Every tag
+ * starts with its type and size and is padded to 8 bytes. Like the version 1
+ * parser this runs before paging is enabled.
+ */
+
+use core::prelude::*;
+use core::mem;
+use kernel::boot::{BootInfo, BootString, MemoryRegion, MemoryKind, Module, Framebuffer, ElfSections, Rsdp, Protocol};
+use platform::multiboot::memory_kind;
+
+/// Value the boot loader leaves in EAX
+pub const BOOTLOADER_MAGIC: u32 = 0x36D76289;
+
+const TAG_END: u32 = 0;
+const TAG_CMDLINE: u32 = 1;
+const TAG_LOADER_NAME: u32 = 2;
+const TAG_MODULE: u32 = 3;
+const TAG_BASIC_MEMORY: u32 = 4;
+const TAG_MMAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+const TAG_ELF_SECTIONS: u32 = 9;
+const TAG_ACPI_OLD: u32 = 14;
+const TAG_ACPI_NEW: u32 = 15;
+
+const UPPER_MEMORY_START: u64 = 0x100000;
+
+#[repr(C, packed)]
+struct Tag
+{
+	kind: u32,
+	size: u32
+}
+
+#[repr(C, packed)]
+struct ModuleTag
+{
+	tag: Tag,
+	mod_start: u32,
+	mod_end: u32
+	// followed by the NUL terminated command line
+}
+
+#[repr(C, packed)]
+struct BasicMemoryTag
+{
+	tag: Tag,
+	mem_lower: u32,
+	mem_upper: u32
+}
+
+#[repr(C, packed)]
+struct MmapTag
+{
+	tag: Tag,
+	entry_size: u32,
+	entry_version: u32
+	// followed by the entries
+}
+
+#[repr(C, packed)]
+struct MmapEntry
+{
+	base_addr: u64,
+	length: u64,
+	kind: u32,
+	reserved: u32
+}
+
+#[repr(C, packed)]
+struct FramebufferTag
+{
+	tag: Tag,
+	address: u64,
+	pitch: u32,
+	width: u32,
+	height: u32,
+	bpp: u8,
+	kind: u8
+}
+
+#[repr(C, packed)]
+struct ElfSectionsTag
+{
+	tag: Tag,
+	// The spec lists 16 bit fields here, but GRUB has always written 32 bit ones
+	count: u32,
+	entry_size: u32,
+	string_index: u32
+	// followed by the section headers
+}
+
+#[repr(C, packed)]
+struct RsdpV1
+{
+	signature: [u8; 8],
+	checksum: u8,
+	oem_id: [u8; 6],
+	revision: u8,
+	rsdt_address: u32
+}
+
+#[repr(C, packed)]
+struct RsdpV2
+{
+	v1: RsdpV1,
+	length: u32,
+	xsdt_address: u64,
+	extended_checksum: u8,
+	reserved: [u8; 3]
+}
+
+/// Parses the information at `address`. Returns None unless `magic` shows
+/// that a Multiboot2 loader started us.
+pub fn parse(magic: u32, address: u32) -> Option<BootInfo>
+{
+	if magic != BOOTLOADER_MAGIC { return None }
+
+	let mut boot = BootInfo::new(Protocol::Multiboot2);
+	let mut basic_memory = None;
+	let mut have_mmap = false;
+	unsafe
+	{
+		let total_size = *(address as *const u32);
+		let end = address + total_size;
+		let mut tag_address = address + 8;
+
+		while tag_address + 8 <= end
+		{
+			let tag = &*(tag_address as *const Tag);
+			// A size smaller than the tag header would never get us further
+			if tag.size < 8 || tag.size > end - tag_address { break }
+			match tag.kind
+			{
+				TAG_END => break,
+				TAG_CMDLINE => boot.cmdline = BootString::from_c_string(tag_address + 8),
+				TAG_LOADER_NAME => boot.bootloader_name = BootString::from_c_string(tag_address + 8),
+				TAG_MODULE =>
+				{
+					let module = &*(tag_address as *const ModuleTag);
+					boot.add_module(Module
+					{
+						start: module.mod_start,
+						end: module.mod_end,
+						cmdline: BootString::from_c_string(tag_address + 16)
+					});
+				},
+				TAG_BASIC_MEMORY =>
+				{
+					let memory = &*(tag_address as *const BasicMemoryTag);
+					basic_memory = Some((memory.mem_lower, memory.mem_upper));
+				},
+				TAG_MMAP =>
+				{
+					parse_mmap(tag_address, &mut boot);
+					have_mmap = true;
+				},
+				TAG_FRAMEBUFFER =>
+				{
+					let framebuffer = &*(tag_address as *const FramebufferTag);
+					boot.framebuffer = Some(Framebuffer
+					{
+						address: framebuffer.address,
+						pitch: framebuffer.pitch,
+						width: framebuffer.width,
+						height: framebuffer.height,
+						bits_per_pixel: framebuffer.bpp,
+						kind: framebuffer.kind
+					});
+				},
+				TAG_ELF_SECTIONS =>
+				{
+					let sections = &*(tag_address as *const ElfSectionsTag);
+					boot.elf_sections = Some(ElfSections
+					{
+						count: sections.count,
+						entry_size: sections.entry_size,
+						string_index: sections.string_index,
+						address: tag_address + 20
+					});
+				},
+				TAG_ACPI_OLD =>
+				{
+					let rsdp = &*((tag_address + 8) as *const RsdpV1);
+					boot.acpi_rsdp = Some(Rsdp { revision: rsdp.revision, rsdt_address: rsdp.rsdt_address, xsdt_address: None });
+				},
+				TAG_ACPI_NEW =>
+				{
+					let rsdp = &*((tag_address + 8) as *const RsdpV2);
+					boot.acpi_rsdp = Some(Rsdp { revision: rsdp.v1.revision, rsdt_address: rsdp.v1.rsdt_address, xsdt_address: Some(rsdp.xsdt_address) });
+				},
+				_ => {},
+			}
+			tag_address += (tag.size + 7) & !7;
+		}
+	}
+
+	if !have_mmap
+	{
+		// Without a map there are only the sizes of the memory below 1MiB and above it
+		if let Some((lower, upper)) = basic_memory
+		{
+			boot.add_memory_region(MemoryRegion { start: 0, length: lower as u64 * 1024, kind: MemoryKind::Available });
+			boot.add_memory_region(MemoryRegion { start: UPPER_MEMORY_START, length: upper as u64 * 1024, kind: MemoryKind::Available });
+		}
+	}
+	Some(boot)
+}
+
+unsafe fn parse_mmap(tag_address: u32, boot: &mut BootInfo)
+{
+	let mmap = &*(tag_address as *const MmapTag);
+	if (mmap.entry_size as usize) < mem::size_of::<MmapEntry>() { return }
+	let end = tag_address + mmap.tag.size;
+	let mut entry = tag_address + 16;
+	// Newer entry versions may grow, so step by the size the loader gives us
+	while entry + mmap.entry_size <= end
+	{
+		let region = &*(entry as *const MmapEntry);
+		boot.add_memory_region(MemoryRegion { start: region.base_addr, length: region.length, kind: memory_kind(region.kind) });
+		entry += mmap.entry_size;
+	}
+}
@@ -0,0 +1,142 @@
+/*
+ * CMOS real-time clock
+ *
+ * See: http://wiki.osdev.org/CMOS
+ */
+
+use core::marker::Copy;
+use core::clone::Clone;
+use core::cmp::PartialEq;
+use platform::io;
+
+static CMOS_ADDRESS: u16 = 0x70;
+static CMOS_DATA: u16 = 0x71;
+
+static REG_SECONDS: u8 = 0x00;
+static REG_MINUTES: u8 = 0x02;
+static REG_HOURS: u8 = 0x04;
+static REG_DAY: u8 = 0x07;
+static REG_MONTH: u8 = 0x08;
+static REG_YEAR: u8 = 0x09;
+static REG_CENTURY: u8 = 0x32;
+static REG_STATUS_A: u8 = 0x0A;
+static REG_STATUS_B: u8 = 0x0B;
+
+static STATUS_A_UPDATING: u8 = 0x80;
+static STATUS_B_24_HOUR: u8 = 0x02;
+static STATUS_B_BINARY: u8 = 0x04;
+static HOUR_PM: u8 = 0x80;
+
+pub struct RtcTime
+{
+	pub second: u8,
+	pub minute: u8,
+	pub hour: u8,
+	pub day: u8,
+	pub month: u8,
+	pub year: u16
+}
+
+impl Copy for RtcTime {}
+impl Clone for RtcTime { fn clone(&self) -> Self { *self } }
+
+impl PartialEq for RtcTime
+{
+	fn eq(&self, other: &RtcTime) -> bool
+	{
+		self.second == other.second && self.minute == other.minute && self.hour == other.hour
+			&& self.day == other.day && self.month == other.month && self.year == other.year
+	}
+}
+
+struct RawTime
+{
+	time: RtcTime,
+	century: u8
+}
+
+impl Copy for RawTime {}
+impl Clone for RawTime { fn clone(&self) -> Self { *self } }
+
+/// Reads the current date and time, in 24-hour binary form
+pub fn read() -> RtcTime
+{
+	// Read until two reads in a row agree, so an update halfway through does not matter
+	let mut last = read_raw();
+	loop
+	{
+		let current = read_raw();
+		if current.time == last.time && current.century == last.century
+		{
+			break;
+		}
+		last = current;
+	}
+
+	let status = read_register(REG_STATUS_B);
+	let binary = status & STATUS_B_BINARY != 0;
+
+	let raw = last.time;
+	let pm = raw.hour & HOUR_PM != 0;
+	let mut hour = convert(raw.hour & !HOUR_PM, binary);
+	if status & STATUS_B_24_HOUR == 0
+	{
+		// 12 AM is midnight and 12 PM is noon
+		hour = hour % 12 + if pm { 12 } else { 0 };
+	}
+
+	let century = convert(last.century, binary);
+	let year = convert(raw.year as u8, binary) as u16;
+	let full_year = if century >= 19 && century <= 21
+	{
+		century as u16 * 100 + year
+	}
+	else
+	{
+		// No usable century register, assume we are in the 21st century
+		2000 + year
+	};
+
+	RtcTime
+	{
+		second: convert(raw.second, binary),
+		minute: convert(raw.minute, binary),
+		hour: hour,
+		day: convert(raw.day, binary),
+		month: convert(raw.month, binary),
+		year: full_year
+	}
+}
+
+fn read_raw() -> RawTime
+{
+	while read_register(REG_STATUS_A) & STATUS_A_UPDATING != 0 {}
+
+	RawTime
+	{
+		time: RtcTime
+		{
+			second: read_register(REG_SECONDS),
+			minute: read_register(REG_MINUTES),
+			hour: read_register(REG_HOURS),
+			day: read_register(REG_DAY),
+			month: read_register(REG_MONTH),
+			year: read_register(REG_YEAR) as u16
+		},
+		century: read_register(REG_CENTURY)
+	}
+}
+
+fn read_register(register: u8) -> u8
+{
+	unsafe
+	{
+		io::outport(CMOS_ADDRESS, register);
+		io::inport(CMOS_DATA)
+	}
+}
+
+fn convert(value: u8, binary: bool) -> u8
+{
+	if binary { value } else { (value & 0x0F) + (value >> 4) * 10 }
+}
@@ -0,0 +1,74 @@
+/*
+ * 16550 UART on the first serial port
+ *
+ * See: http://wiki.osdev.org/Serial_Ports
+ *
+ * Output only, and polled: a byte is written once the transmit buffer is
+ * empty. QEMU's -serial stdio shows it on the host terminal.
+ */
+
+use core::prelude::*;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+use platform::io;
+
+static COM1: u16 = 0x3F8;
+
+static REG_DATA: u16 = 0;
+static REG_INTERRUPT_ENABLE: u16 = 1;
+static REG_FIFO_CONTROL: u16 = 2;
+static REG_LINE_CONTROL: u16 = 3;
+static REG_MODEM_CONTROL: u16 = 4;
+static REG_LINE_STATUS: u16 = 5;
+
+static LINE_DLAB: u8 = 0x80;
+static LINE_8N1: u8 = 0x03;
+static STATUS_TRANSMIT_EMPTY: u8 = 0x20;
+
+static BASE_BAUD: u32 = 115200;
+static BAUD: u32 = 115200;
+
+static INITIALIZED: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Sets the port up for 8N1 at BAUD. Only the first call does anything.
+pub fn init()
+{
+	if INITIALIZED.swap(true, Ordering::SeqCst) { return }
+
+	let divisor = BASE_BAUD / BAUD;
+	unsafe
+	{
+		io::outport(COM1 + REG_INTERRUPT_ENABLE, 0x00);
+		io::outport(COM1 + REG_LINE_CONTROL, LINE_DLAB);
+		io::outport(COM1 + REG_DATA, divisor as u8);
+		io::outport(COM1 + REG_INTERRUPT_ENABLE, (divisor >> 8) as u8);
+		io::outport(COM1 + REG_LINE_CONTROL, LINE_8N1);
+		io::outport(COM1 + REG_FIFO_CONTROL, 0xC7); // enable and clear, 14 byte threshold
+		io::outport(COM1 + REG_MODEM_CONTROL, 0x03); // DTR and RTS
+	}
+}
+
+pub fn write_byte(byte: u8)
+{
+	unsafe
+	{
+		while io::inport(COM1 + REG_LINE_STATUS) & STATUS_TRANSMIT_EMPTY == 0 {}
+		io::outport(COM1 + REG_DATA, byte);
+	}
+}
+
+pub struct SerialWriter;
+
+impl fmt::Write for SerialWriter
+{
+	fn write_str(&mut self, s: &str) -> fmt::Result
+	{
+		for b in s.bytes()
+		{
+			// Terminals expect a carriage return before every line feed
+			if b == b'\n' { write_byte(b'\r'); }
+			write_byte(b);
+		}
+		Ok(())
+	}
+}
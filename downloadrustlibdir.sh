@@ -5,6 +5,15 @@ URL="http://static.rust-lang.org/dist/rust-nightly-${TARGET}.tar.gz"
 TARPATH=rust-nightly-${TARGET}/rustc/lib/rustlib/${TARGET}/lib/
 COMPONENTS=6
 FOLDER=rustlibdir
+SRC_URL="http://static.rust-lang.org/dist/rustc-nightly-src.tar.gz"
+SRC_FOLDER=rustsrc
+
+# liballoc and libcollections are rebuilt from source for the kernel
+if [ ! -d "$SRC_FOLDER" ]
+then
+	mkdir $SRC_FOLDER
+	curl $SRC_URL | tar -xz --strip-components 1 -C $SRC_FOLDER
+fi
 
 RUST_TARGET=$(rustc --version --verbose | grep host | awk '{print $2}')
 
@@ -0,0 +1,260 @@
+/*
+ * Information handed over by the boot loader
+ *
+ * The kernel can be started through Multiboot or Multiboot2. The parsers for
+ * both protocols in platform fill in the same BootInfo before the
+ * heap exists, so it uses fixed-size storage and copies every string out of
+ * the loader's memory. Once the heap is up it is kept for the rest of the
+ * kernel to query with `info()`.
+ */
+
+use core::prelude::*;
+use core::str;
+use kernel::sync::Once;
+
+pub const MAX_MEMORY_REGIONS: usize = 32;
+pub const MAX_MODULES: usize = 8;
+const STRING_SIZE: usize = 128;
+
+pub enum MemoryKind
+{
+	Available,
+	Reserved,
+	AcpiReclaimable,
+	AcpiNvs,
+	Defective,
+}
+
+impl Copy for MemoryKind {}
+impl Clone for MemoryKind { fn clone(&self) -> Self { *self } }
+
+pub struct MemoryRegion
+{
+	pub start: u64,
+	pub length: u64,
+	pub kind: MemoryKind
+}
+
+impl Copy for MemoryRegion {}
+impl Clone for MemoryRegion { fn clone(&self) -> Self { *self } }
+
+/// A NUL-free string of at most STRING_SIZE bytes, longer ones are cut off
+pub struct BootString
+{
+	bytes: [u8; STRING_SIZE],
+	length: usize
+}
+
+impl Copy for BootString {}
+impl Clone for BootString { fn clone(&self) -> Self { *self } }
+
+impl BootString
+{
+	pub fn empty() -> BootString
+	{
+		BootString { bytes: [0; STRING_SIZE], length: 0 }
+	}
+
+	/// Copies the NUL terminated string at physical address `address`
+	pub unsafe fn from_c_string(address: u32) -> BootString
+	{
+		let mut string = BootString::empty();
+		if address == 0 { return string }
+
+		let source = address as *const u8;
+		while string.length < STRING_SIZE
+		{
+			let byte = *source.offset(string.length as isize);
+			if byte == 0 { break }
+			string.bytes[string.length] = byte;
+			string.length += 1;
+		}
+		string
+	}
+
+	pub fn as_str(&self) -> &str
+	{
+		// A string cut off in the middle of a character loses that character
+		let mut length = self.length;
+		loop
+		{
+			match str::from_utf8(&self.bytes[.. length])
+			{
+				Ok(s) => return s,
+				Err(_) => length -= 1,
+			}
+		}
+	}
+}
+
+/// A file loaded next to the kernel, identity mapped and read-only
+pub struct Module
+{
+	pub start: u32,
+	pub end: u32,
+	pub cmdline: BootString
+}
+
+impl Copy for Module {}
+impl Clone for Module { fn clone(&self) -> Self { *self } }
+
+impl Module
+{
+	pub fn data(&self) -> &'static [u8]
+	{
+		unsafe { ::core::slice::from_raw_parts(self.start as *const u8, (self.end - self.start) as usize) }
+	}
+}
+
+pub struct Framebuffer
+{
+	pub address: u64,
+	pub pitch: u32,
+	pub width: u32,
+	pub height: u32,
+	pub bits_per_pixel: u8,
+	/// 0 for indexed colours, 1 for direct RGB, 2 for EGA text
+	pub kind: u8
+}
+
+impl Copy for Framebuffer {}
+impl Clone for Framebuffer { fn clone(&self) -> Self { *self } }
+
+/// Where the boot loader put the kernel's ELF section headers, see mmu::setup
+pub struct ElfSections
+{
+	/// Physical address of the first section header
+	pub address: u32,
+	pub count: u32,
+	pub entry_size: u32,
+	/// Index of the section holding the section names
+	pub string_index: u32
+}
+
+impl Copy for ElfSections {}
+impl Clone for ElfSections { fn clone(&self) -> Self { *self } }
+
+/// The ACPI Root System Description Pointer. The boot loader hands over a
+/// copy, so only the addresses of the tables it points to are kept.
+pub struct Rsdp
+{
+	pub revision: u8,
+	pub rsdt_address: u32,
+	/// Only set from ACPI 2.0 on
+	pub xsdt_address: Option<u64>
+}
+
+impl Copy for Rsdp {}
+impl Clone for Rsdp { fn clone(&self) -> Self { *self } }
+
+pub enum Protocol
+{
+	Multiboot,
+	Multiboot2,
+}
+
+impl Copy for Protocol {}
+impl Clone for Protocol { fn clone(&self) -> Self { *self } }
+
+pub struct BootInfo
+{
+	pub protocol: Protocol,
+	memory_regions: [MemoryRegion; MAX_MEMORY_REGIONS],
+	memory_region_count: usize,
+	modules: [Module; MAX_MODULES],
+	module_count: usize,
+	pub cmdline: BootString,
+	pub bootloader_name: BootString,
+	pub framebuffer: Option<Framebuffer>,
+	pub elf_sections: Option<ElfSections>,
+	pub acpi_rsdp: Option<Rsdp>
+}
+
+impl Copy for BootInfo {}
+impl Clone for BootInfo { fn clone(&self) -> Self { *self } }
+
+impl BootInfo
+{
+	pub fn new(protocol: Protocol) -> BootInfo
+	{
+		BootInfo
+		{
+			protocol: protocol,
+			memory_regions: [MemoryRegion { start: 0, length: 0, kind: MemoryKind::Reserved }; MAX_MEMORY_REGIONS],
+			memory_region_count: 0,
+			modules: [Module { start: 0, end: 0, cmdline: BootString::empty() }; MAX_MODULES],
+			module_count: 0,
+			cmdline: BootString::empty(),
+			bootloader_name: BootString::empty(),
+			framebuffer: None,
+			elf_sections: None,
+			acpi_rsdp: None
+		}
+	}
+
+	pub fn memory_regions(&self) -> &[MemoryRegion]
+	{
+		&self.memory_regions[.. self.memory_region_count]
+	}
+
+	pub fn modules(&self) -> &[Module]
+	{
+		&self.modules[.. self.module_count]
+	}
+
+	/// Returns false when the table is full and the region was dropped
+	pub fn add_memory_region(&mut self, region: MemoryRegion) -> bool
+	{
+		if self.memory_region_count == MAX_MEMORY_REGIONS
+		{
+			log!(Warning, "Ignoring memory region at 0x{:x}, only {} fit", region.start, MAX_MEMORY_REGIONS);
+			return false;
+		}
+		self.memory_regions[self.memory_region_count] = region;
+		self.memory_region_count += 1;
+		true
+	}
+
+	/// Returns false when the module was dropped, because the table is full or
+	/// the module ends before it starts
+	pub fn add_module(&mut self, module: Module) -> bool
+	{
+		if module.end < module.start
+		{
+			log!(Warning, "Ignoring module at 0x{:x} that ends at 0x{:x}", module.start, module.end);
+			return false;
+		}
+		if self.module_count == MAX_MODULES
+		{
+			log!(Warning, "Ignoring module at 0x{:x}, only {} fit", module.start, MAX_MODULES);
+			return false;
+		}
+		self.modules[self.module_count] = module;
+		self.module_count += 1;
+		true
+	}
+
+	/// Bytes of memory the boot loader reported as available
+	pub fn available_memory(&self) -> u64
+	{
+		self.memory_regions().iter().filter(|r| match r.kind { MemoryKind::Available => true, _ => false }).fold(0, |total, r| total + r.length)
+	}
+}
+
+static BOOT_INFO: Once<BootInfo> = once!();
+
+/// Keeps `info` for `info()`, needs the heap
+pub fn init(info: &BootInfo)
+{
+	let copy = *info;
+	BOOT_INFO.call_once(move || copy);
+}
+
+pub fn info() -> &'static BootInfo
+{
+	match BOOT_INFO.get()
+	{
+		Some(info) => info,
+		None => panic!("Boot information used before boot::init"),
+	}
+}
@@ -0,0 +1,201 @@
+/*
+ * Kernel command line
+ *
+ * The boot loader passes a line like `/boot/kernel loglevel=debug selftest`.
+ * Options are separated by spaces and are either a bare name or name=value,
+ * values with spaces in them can be put in double quotes.
+ *
+ * Subsystems declare the options they understand as static Parameters,
+ * register them during init and read them whenever they like. Everything
+ * else on the line is reported by `check` once the kernel is up. The line is
+ * taken from the boot information, so nothing can be read before boot::init.
+ */
+
+use core::prelude::*;
+use collections::vec::Vec;
+use kernel::boot;
+use kernel::sync::{IrqLock, Once};
+
+pub enum Kind
+{
+	/// Set by its bare name, or explicitly with =1/0, =on/off, =yes/no, =true/false
+	Flag,
+	/// Decimal, or hexadecimal with 0x in front
+	Number,
+	Text,
+}
+
+impl Copy for Kind {}
+impl Clone for Kind { fn clone(&self) -> Self { *self } }
+
+pub struct Parameter
+{
+	pub name: &'static str,
+	pub kind: Kind,
+	pub description: &'static str
+}
+
+static PARAMETERS: Once<IrqLock<Vec<&'static Parameter>>> = once!();
+
+fn parameters_lock() -> &'static IrqLock<Vec<&'static Parameter>>
+{
+	PARAMETERS.call_once(|| IrqLock::new(Vec::new()))
+}
+
+/// Makes `parameter` known, so that `check` does not complain about it
+pub fn register(parameter: &'static Parameter)
+{
+	let mut parameters = parameters_lock().lock();
+	if !parameters.iter().any(|p| p.name == parameter.name)
+	{
+		parameters.push(parameter);
+	}
+}
+
+/// Every registered parameter, in the order of registration
+pub fn parameters() -> Vec<&'static Parameter>
+{
+	parameters_lock().lock().clone()
+}
+
+impl Parameter
+{
+	/// The value of the last occurrence, None for a bare name and for
+	/// options missing from the line
+	pub fn text(&self) -> Option<&'static str>
+	{
+		match self.raw() { Some(value) => value, None => None }
+	}
+
+	/// Whether the option is on the line, with or without a value
+	pub fn is_present(&self) -> bool
+	{
+		self.raw().is_some()
+	}
+
+	pub fn flag(&self) -> bool
+	{
+		match self.raw()
+		{
+			Some(None) => true,
+			Some(Some(value)) => parse_flag(value).unwrap_or(false),
+			None => false,
+		}
+	}
+
+	pub fn number(&self) -> Option<u32>
+	{
+		match self.text()
+		{
+			Some(value) => parse_number(value),
+			None => None,
+		}
+	}
+
+	// Later occurrences override earlier ones
+	fn raw(&self) -> Option<Option<&'static str>>
+	{
+		let mut result = None;
+		for (name, value) in options()
+		{
+			if name == self.name { result = Some(value); }
+		}
+		result
+	}
+}
+
+/// Logs a warning for every option that no subsystem registered, and for
+/// values that do not match the kind of their parameter
+pub fn check()
+{
+	let mut first = true;
+	for (name, value) in options()
+	{
+		let parameter = parameters_lock().lock().iter().find(|p| p.name == name).map(|p| *p);
+		match parameter
+		{
+			Some(parameter) => match (parameter.kind, value)
+			{
+				(Kind::Flag, Some(value)) if parse_flag(value).is_none() =>
+					log!(Warning, "Option {} is a flag, ignoring value '{}'", name, value),
+				(Kind::Number, Some(value)) if parse_number(value).is_none() =>
+					log!(Warning, "Option {} needs a number, got '{}'", name, value),
+				(Kind::Number, None) | (Kind::Text, None) =>
+					log!(Warning, "Option {} needs a value", name),
+				_ => {},
+			},
+			// Boot loaders put the path of the kernel image in front
+			None if first && value.is_none() => {},
+			None => log!(Warning, "Unknown kernel option {}", name),
+		}
+		first = false;
+	}
+}
+
+/// The whole line as the boot loader passed it
+pub fn line() -> &'static str
+{
+	boot::info().cmdline.as_str()
+}
+
+/// Iterates over the options on the line as (name, value) pairs
+pub fn options() -> Options
+{
+	Options { rest: line() }
+}
+
+pub struct Options
+{
+	rest: &'static str
+}
+
+impl Iterator for Options
+{
+	type Item = (&'static str, Option<&'static str>);
+
+	fn next(&mut self) -> Option<(&'static str, Option<&'static str>)>
+	{
+		let rest = self.rest.trim_left_matches(' ');
+		if rest.len() == 0 { return None }
+
+		let bytes = rest.as_bytes();
+		let mut end = 0;
+		let mut equals = None;
+		let mut quoted = false;
+		while end < bytes.len() && (quoted || bytes[end] != b' ')
+		{
+			if bytes[end] == b'=' && equals.is_none() { equals = Some(end); }
+			if bytes[end] == b'"' && equals.is_some() { quoted = !quoted; }
+			end += 1;
+		}
+		self.rest = &rest[end ..];
+
+		Some(match equals
+		{
+			Some(equals) => (&rest[.. equals], Some(rest[equals + 1 .. end].trim_matches('"'))),
+			None => (&rest[.. end], None),
+		})
+	}
+}
+
+fn parse_flag(value: &str) -> Option<bool>
+{
+	match value
+	{
+		"1" | "on" | "yes" | "true" => Some(true),
+		"0" | "off" | "no" | "false" => Some(false),
+		_ => None,
+	}
+}
+
+fn parse_number(value: &str) -> Option<u32>
+{
+	if value.starts_with("0x")
+	{
+		u32::from_str_radix(&value[2 ..], 16).ok()
+	}
+	else
+	{
+		value.parse().ok()
+	}
+}
@@ -0,0 +1,308 @@
+/*
+ * Loader for statically linked i386 ELF executables
+ *
+ * The image is checked, its PT_LOAD segments are copied into a fresh address
+ * space and a stack holding argc, argv, envp and an empty auxiliary vector is
+ * built below USER_END, the way the System V i386 ABI expects it at _start.
+ *
+ * Segments have to lie between USER_START and the stack, so programs linked
+ * at the usual 0x08048000 are rejected. Link them with user/link.ld or with
+ * `-Ttext-segment=0x40000000`.
+ */
+
+use core::prelude::*;
+use core::{mem, ptr};
+use collections::vec::Vec;
+use platform::mmu::{frame, paging};
+use kernel::thread;
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELF_CLASS_32: u8 = 1;
+const ELF_DATA_LSB: u8 = 1;
+const ELF_VERSION_CURRENT: u32 = 1;
+const ELF_TYPE_EXEC: u16 = 2;
+const ELF_MACHINE_386: u16 = 3;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1 << 0;
+const PF_W: u32 = 1 << 1;
+
+pub const USER_STACK_TOP: u32 = paging::USER_END;
+pub const USER_STACK_SIZE: u32 = 64 * 1024;
+// Strings and pointers for argv and envp may use this much of the stack
+const MAX_ARGUMENT_SIZE: u32 = USER_STACK_SIZE / 2;
+
+#[repr(C)]
+struct ElfHeader
+{
+	ident: [u8; 16],
+	kind: u16,
+	machine: u16,
+	version: u32,
+	entry: u32,
+	phoff: u32,
+	shoff: u32,
+	flags: u32,
+	ehsize: u16,
+	phentsize: u16,
+	phnum: u16,
+	shentsize: u16,
+	shnum: u16,
+	shstrndx: u16
+}
+
+#[repr(C)]
+struct ProgramHeader
+{
+	kind: u32,
+	offset: u32,
+	vaddr: u32,
+	paddr: u32,
+	filesz: u32,
+	memsz: u32,
+	flags: u32,
+	align: u32
+}
+
+impl Copy for ProgramHeader {}
+impl Clone for ProgramHeader { fn clone(&self) -> Self { *self } }
+
+pub enum ElfError
+{
+	TooShort,
+	BadMagic,
+	/// Not a 32-bit little-endian i386 executable
+	Unsupported,
+	/// A segment lies outside the image or outside user space
+	BadSegment,
+	/// The entry point is not in an executable segment
+	BadEntry,
+	ArgumentsTooLong,
+	OutOfMemory,
+}
+
+impl Copy for ElfError {}
+impl Clone for ElfError { fn clone(&self) -> Self { *self } }
+
+impl ElfError
+{
+	pub fn description(&self) -> &'static str
+	{
+		match *self
+		{
+			ElfError::TooShort => "image is truncated",
+			ElfError::BadMagic => "not an ELF image",
+			ElfError::Unsupported => "not an i386 executable",
+			ElfError::BadSegment => "segment outside the image or user space",
+			ElfError::BadEntry => "entry point outside the executable segments",
+			ElfError::ArgumentsTooLong => "arguments do not fit on the stack",
+			ElfError::OutOfMemory => "out of memory",
+		}
+	}
+}
+
+/// An image loaded into its own address space, ready to run
+pub struct Program
+{
+	pub directory: u32,
+	pub entry: u32,
+	pub stack_pointer: u32
+}
+
+impl Copy for Program {}
+impl Clone for Program { fn clone(&self) -> Self { *self } }
+
+/// Creates an address space holding the segments of `image` and a stack with
+/// `argv` and `envp`
+pub fn load(image: &[u8], argv: &[&str], envp: &[&str]) -> Result<Program, ElfError>
+{
+	let segments = try!(check_image(image));
+
+	let directory = match paging::create_address_space()
+	{
+		Some(directory) => directory,
+		None => return Err(ElfError::OutOfMemory),
+	};
+
+	// The new address space is filled in while it is loaded
+	let previous = thread::set_address_space(directory);
+	let result = populate(image, &segments, argv, envp);
+	thread::set_address_space(previous);
+
+	match result
+	{
+		Ok(stack_pointer) => Ok(Program { directory: directory, entry: header(image).entry, stack_pointer: stack_pointer }),
+		Err(error) =>
+		{
+			paging::destroy_address_space(directory);
+			Err(error)
+		},
+	}
+}
+
+fn header(image: &[u8]) -> &ElfHeader
+{
+	unsafe { &*(image.as_ptr() as *const ElfHeader) }
+}
+
+/// Validates the headers and returns the PT_LOAD segments
+fn check_image(image: &[u8]) -> Result<Vec<ProgramHeader>, ElfError>
+{
+	if image.len() < mem::size_of::<ElfHeader>() { return Err(ElfError::TooShort) }
+
+	let header = header(image);
+	if header.ident[0 .. 4] != ELF_MAGIC[..] { return Err(ElfError::BadMagic) }
+	if header.ident[4] != ELF_CLASS_32 || header.ident[5] != ELF_DATA_LSB
+		|| header.version != ELF_VERSION_CURRENT || header.kind != ELF_TYPE_EXEC
+		|| header.machine != ELF_MACHINE_386
+		|| header.phentsize as usize != mem::size_of::<ProgramHeader>()
+	{
+		return Err(ElfError::Unsupported)
+	}
+	if !paging::is_user_address(header.entry) { return Err(ElfError::BadEntry) }
+
+	// The header fields are 32 bits like usize, so every sum has to be checked
+	let table_end = (header.phnum as usize).checked_mul(mem::size_of::<ProgramHeader>())
+		.and_then(|size| (header.phoff as usize).checked_add(size));
+	match table_end
+	{
+		Some(end) if end <= image.len() => {},
+		_ => return Err(ElfError::TooShort),
+	}
+
+	let mut segments = Vec::new();
+	for i in (0 .. header.phnum as usize)
+	{
+		let segment = unsafe
+		{
+			ptr::read((image.as_ptr() as usize + header.phoff as usize + i * mem::size_of::<ProgramHeader>()) as *const ProgramHeader)
+		};
+		if segment.kind != PT_LOAD || segment.memsz == 0 { continue }
+
+		let end = match segment.vaddr.checked_add(segment.memsz) { Some(end) => end, None => return Err(ElfError::BadSegment) };
+		let file_end = match segment.offset.checked_add(segment.filesz) { Some(end) => end, None => return Err(ElfError::BadSegment) };
+		if segment.filesz > segment.memsz
+			|| file_end as usize > image.len()
+			|| segment.vaddr < paging::USER_START || end > USER_STACK_TOP - USER_STACK_SIZE
+		{
+			return Err(ElfError::BadSegment)
+		}
+		segments.push(segment);
+	}
+
+	let entry = header.entry;
+	if !segments.iter().any(|s| s.flags & PF_X != 0 && entry >= s.vaddr && entry - s.vaddr < s.memsz)
+	{
+		return Err(ElfError::BadEntry)
+	}
+	Ok(segments)
+}
+
+/// Fills the loaded address space. Returns the initial stack pointer.
+fn populate(image: &[u8], segments: &[ProgramHeader], argv: &[&str], envp: &[&str]) -> Result<u32, ElfError>
+{
+	// Pages start out writable so they can be filled, and read-only segments
+	// are protected once everything is copied
+	for segment in segments.iter()
+	{
+		try!(map_range(segment.vaddr, segment.vaddr + segment.memsz));
+		unsafe
+		{
+			ptr::copy_nonoverlapping(image.as_ptr().offset(segment.offset as isize), segment.vaddr as *mut u8, segment.filesz as usize);
+		}
+	}
+	for segment in segments.iter()
+	{
+		if segment.flags & PF_W != 0 { continue }
+		let mut page = segment.vaddr & !(paging::PAGE_SIZE - 1);
+		while page < segment.vaddr + segment.memsz
+		{
+			// A page shared with a writable segment stays writable
+			if !segments.iter().any(|s| s.flags & PF_W != 0 && overlaps_page(s, page))
+			{
+				if let Some(phys) = paging::translate(page)
+				{
+					paging::map(page, phys, paging::USER);
+				}
+			}
+			page += paging::PAGE_SIZE;
+		}
+	}
+
+	try!(map_range(USER_STACK_TOP - USER_STACK_SIZE, USER_STACK_TOP));
+	build_stack(argv, envp)
+}
+
+fn overlaps_page(segment: &ProgramHeader, page: u32) -> bool
+{
+	segment.vaddr < page + paging::PAGE_SIZE && segment.vaddr + segment.memsz > page
+}
+
+/// Backs [start, end) with zeroed, writable user pages
+fn map_range(start: u32, end: u32) -> Result<(), ElfError>
+{
+	let mut page = start & !(paging::PAGE_SIZE - 1);
+	while page < end
+	{
+		if paging::page_entry(page).is_none()
+		{
+			let phys = match frame::alloc_frame() { Some(f) => f, None => return Err(ElfError::OutOfMemory) };
+			paging::map(page, phys, paging::USER | paging::WRITABLE);
+			unsafe
+			{
+				for i in (0 .. paging::PAGE_SIZE / 4)
+				{
+					*((page + i * 4) as *mut u32) = 0;
+				}
+			}
+		}
+		page += paging::PAGE_SIZE;
+	}
+	Ok(())
+}
+
+/// Lays out, from the top down: the strings, then argc, argv[], NULL, envp[],
+/// NULL and an AT_NULL auxiliary vector entry at the 16 byte aligned stack pointer
+fn build_stack(argv: &[&str], envp: &[&str]) -> Result<u32, ElfError>
+{
+	let strings = argv.iter().chain(envp.iter()).fold(0, |total, s| total + s.len() as u32 + 1);
+	let words = 1 + argv.len() as u32 + 1 + envp.len() as u32 + 1 + 2;
+	if strings + words * 4 + 16 > MAX_ARGUMENT_SIZE { return Err(ElfError::ArgumentsTooLong) }
+
+	let mut sp = USER_STACK_TOP;
+	let envp_pointers = push_strings(&mut sp, envp);
+	let argv_pointers = push_strings(&mut sp, argv);
+
+	sp = (sp - words * 4) & !0xF;
+	let mut slot = sp as *mut u32;
+	unsafe
+	{
+		let mut push = |value: u32| { *slot = value; slot = slot.offset(1); };
+		push(argv.len() as u32);
+		for &pointer in argv_pointers.iter() { push(pointer); }
+		push(0);
+		for &pointer in envp_pointers.iter() { push(pointer); }
+		push(0);
+		// AT_NULL
+		push(0);
+		push(0);
+	}
+	Ok(sp)
+}
+
+/// Copies NUL terminated strings below `sp` and returns their addresses
+fn push_strings(sp: &mut u32, strings: &[&str]) -> Vec<u32>
+{
+	let mut pointers = Vec::with_capacity(strings.len());
+	for string in strings.iter()
+	{
+		*sp -= string.len() as u32 + 1;
+		unsafe
+		{
+			ptr::copy_nonoverlapping(string.as_ptr(), *sp as *mut u8, string.len());
+			*((*sp + string.len() as u32) as *mut u8) = 0;
+		}
+		pointers.push(*sp);
+	}
+	pointers
+}
@@ -0,0 +1,126 @@
+/*
+ * The text console and the keyboard as files
+ */
+
+use core::prelude::*;
+use platform::vga::Color;
+use kernel::fs::{File, FileType, FsError, Stat, OPEN_READ, OPEN_WRITE};
+use kernel::keyboard;
+use kernel::stdio::StdioWriter;
+use kernel::sync::Mutex;
+
+// Output of user programs goes below the clock and above the log lines
+static CONSOLE: Mutex<StdioWriter> = mutex!(StdioWriter { xpos: 0, ypos: 12, fg: Color::LightGray, bg: Color::Black });
+
+fn device_stat() -> Stat
+{
+	Stat { inode: 0, kind: FileType::CharDevice, mode: 0o620, size: 0 }
+}
+
+/// Writes to the screen, `error` selects the colour used for stderr
+pub struct ConsoleOutput
+{
+	pub error: bool
+}
+
+impl File for ConsoleOutput
+{
+	fn read(&mut self, _: &mut [u8]) -> Result<usize, FsError>
+	{
+		Err(FsError::WrongMode)
+	}
+
+	fn write(&mut self, buffer: &[u8]) -> Result<usize, FsError>
+	{
+		let mut console = CONSOLE.lock();
+		console.fg = if self.error { Color::LightRed } else { Color::LightGray };
+		for &byte in buffer.iter()
+		{
+			match byte
+			{
+				b'\n' => console.crlf(),
+				b'\t' => console.tab(),
+				0x08 => console.backspace(),
+				_ => console.print_char(byte as char),
+			}
+		}
+		Ok(buffer.len())
+	}
+
+	fn stat(&self) -> Stat
+	{
+		device_stat()
+	}
+}
+
+/// Characters typed on the keyboard
+pub struct ConsoleInput;
+
+impl File for ConsoleInput
+{
+	/// Blocks until at least one key is typed and stops after a newline
+	fn read(&mut self, buffer: &mut [u8]) -> Result<usize, FsError>
+	{
+		let mut count = 0;
+		while count < buffer.len()
+		{
+			let c = match keyboard::read_char()
+			{
+				Some(c) => c,
+				None => return Err(FsError::Interrupted),
+			};
+			if c as u32 > 0x7F { continue }
+			buffer[count] = c as u8;
+			count += 1;
+			if c == '\n' { break }
+		}
+		Ok(count)
+	}
+
+	fn write(&mut self, _: &[u8]) -> Result<usize, FsError>
+	{
+		Err(FsError::WrongMode)
+	}
+
+	fn stat(&self) -> Stat
+	{
+		device_stat()
+	}
+}
+
+/// Keyboard and screen together, for /dev/console
+pub struct Console
+{
+	input: ConsoleInput,
+	output: ConsoleOutput,
+	flags: u32
+}
+
+impl Console
+{
+	/// Reads and writes as allowed by the OPEN_READ and OPEN_WRITE in `flags`
+	pub fn new(flags: u32) -> Console
+	{
+		Console { input: ConsoleInput, output: ConsoleOutput { error: false }, flags: flags }
+	}
+}
+
+impl File for Console
+{
+	fn read(&mut self, buffer: &mut [u8]) -> Result<usize, FsError>
+	{
+		if self.flags & OPEN_READ == 0 { return Err(FsError::WrongMode) }
+		self.input.read(buffer)
+	}
+
+	fn write(&mut self, buffer: &[u8]) -> Result<usize, FsError>
+	{
+		if self.flags & OPEN_WRITE == 0 { return Err(FsError::WrongMode) }
+		self.output.write(buffer)
+	}
+
+	fn stat(&self) -> Stat
+	{
+		device_stat()
+	}
+}
@@ -0,0 +1,569 @@
+/*
+ * Device filesystem
+ *
+ * A flat directory of devices, usually mounted at /dev. Character devices
+ * are registered with a function that opens them, block devices with a
+ * BlockDevice, which devfs turns into a file that can be read and written at
+ * any offset. Devices registered after mounting show up right away.
+ *
+ * Built in are console, kbd, serial, null, zero and random, and a read-only
+ * block device ram0, ram1, ... for every boot module.
+ */
+
+use core::prelude::*;
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+use collections::string::String;
+use collections::vec::Vec;
+use platform::cpu;
+use platform::serial;
+use kernel::boot;
+use kernel::fs::{DirEntry, File, FileSystem, FileType, FsError, Inode, InodeRef, SeekFrom, Stat, OPEN_READ, OPEN_WRITE};
+use kernel::fs::console::Console;
+use kernel::keyboard;
+use kernel::keyboard::{KeyboardAction, KeyboardKey};
+use kernel::sync::{IrqLock, Mutex, Once};
+
+const ROOT_INODE: u64 = 1;
+const DEVICE_MODE: u32 = 0o666;
+
+pub type BlockDeviceRef = Arc<Box<BlockDevice>>;
+
+/// A disk or anything else read and written in blocks
+pub trait BlockDevice: Send + Sync
+{
+	fn block_size(&self) -> u32;
+	fn block_count(&self) -> u64;
+
+	/// Fills `buffer`, a whole number of blocks long, starting at block `first`
+	fn read_blocks(&self, first: u64, buffer: &mut [u8]) -> Result<(), FsError>;
+
+	fn write_blocks(&self, _first: u64, _buffer: &[u8]) -> Result<(), FsError> { Err(FsError::ReadOnly) }
+}
+
+enum Device
+{
+	Char(fn(u32) -> Result<Box<File>, FsError>),
+	Block(BlockDeviceRef),
+}
+
+struct DeviceEntry
+{
+	name: String,
+	inode: u64,
+	device: Device
+}
+
+struct Registry
+{
+	devices: Vec<DeviceEntry>,
+	next_inode: u64
+}
+
+static REGISTRY: Once<Mutex<Registry>> = once!();
+
+fn registry() -> &'static Mutex<Registry>
+{
+	REGISTRY.call_once(|| Mutex::new(Registry { devices: Vec::new(), next_inode: ROOT_INODE + 1 }))
+}
+
+fn register(name: &str, device: Device)
+{
+	let mut registry = registry().lock();
+	if registry.devices.iter().any(|entry| entry.name == name)
+	{
+		log!(Warning, "Device {} is already registered", name);
+		return;
+	}
+	let inode = registry.next_inode;
+	registry.next_inode += 1;
+	registry.devices.push(DeviceEntry { name: String::from_str(name), inode: inode, device: device });
+}
+
+/// Adds a character device, `open` gets the flags passed to fs::open
+pub fn register_char_device(name: &str, open: fn(u32) -> Result<Box<File>, FsError>)
+{
+	register(name, Device::Char(open));
+}
+
+pub fn register_block_device(name: &str, device: Box<BlockDevice>)
+{
+	register(name, Device::Block(Arc::new(device)));
+}
+
+/// Registers the built in devices
+pub fn init()
+{
+	register_char_device("console", open_console);
+	register_char_device("kbd", open_keyboard);
+	register_char_device("serial", open_serial);
+	register_char_device("null", open_null);
+	register_char_device("zero", open_zero);
+	register_char_device("random", open_random);
+
+	for (i, module) in boot::info().modules().iter().enumerate()
+	{
+		register_block_device(&format!("ram{}", i), Box::new(MemoryDisk { data: module.data() }) as Box<BlockDevice>);
+	}
+}
+
+pub struct DevFs;
+
+impl FileSystem for DevFs
+{
+	fn name(&self) -> &'static str
+	{
+		"devfs"
+	}
+
+	fn root(&self) -> InodeRef
+	{
+		Arc::new(Box::new(DevRoot) as Box<Inode>)
+	}
+}
+
+struct DevRoot;
+
+impl Inode for DevRoot
+{
+	fn stat(&self) -> Stat
+	{
+		Stat { inode: ROOT_INODE, kind: FileType::Directory, mode: 0o755, size: registry().lock().devices.len() as u64 }
+	}
+
+	fn lookup(&self, name: &str) -> Result<InodeRef, FsError>
+	{
+		let registry = registry().lock();
+		match registry.devices.iter().find(|entry| entry.name == name)
+		{
+			Some(entry) =>
+			{
+				let device = match entry.device
+				{
+					Device::Char(open) => Device::Char(open),
+					Device::Block(ref device) => Device::Block(device.clone()),
+				};
+				Ok(Arc::new(Box::new(DevInode { inode: entry.inode, device: device }) as Box<Inode>))
+			},
+			None => Err(FsError::NotFound),
+		}
+	}
+
+	fn readdir(&self) -> Result<Vec<DirEntry>, FsError>
+	{
+		Ok(registry().lock().devices.iter().map(|entry| DirEntry
+		{
+			name: entry.name.clone(),
+			inode: entry.inode,
+			kind: device_type(&entry.device)
+		}).collect())
+	}
+}
+
+fn device_type(device: &Device) -> FileType
+{
+	match *device
+	{
+		Device::Char(_) => FileType::CharDevice,
+		Device::Block(_) => FileType::BlockDevice,
+	}
+}
+
+struct DevInode
+{
+	inode: u64,
+	device: Device
+}
+
+impl Inode for DevInode
+{
+	fn stat(&self) -> Stat
+	{
+		let size = match self.device
+		{
+			Device::Char(_) => 0,
+			Device::Block(ref device) => device.block_count() * device.block_size() as u64,
+		};
+		Stat { inode: self.inode, kind: device_type(&self.device), mode: DEVICE_MODE, size: size }
+	}
+
+	fn open(&self, flags: u32) -> Option<Result<Box<File>, FsError>>
+	{
+		Some(match self.device
+		{
+			Device::Char(open) => open(flags),
+			Device::Block(ref device) => Ok(Box::new(BlockFile { device: device.clone(), inode: self.inode, flags: flags, position: 0 }) as Box<File>),
+		})
+	}
+}
+
+fn char_stat() -> Stat
+{
+	Stat { inode: 0, kind: FileType::CharDevice, mode: DEVICE_MODE, size: 0 }
+}
+
+fn open_console(flags: u32) -> Result<Box<File>, FsError>
+{
+	Ok(Box::new(Console::new(flags)) as Box<File>)
+}
+
+/// Raw key events, four bytes each: 1 for a press or 0 for a release, the
+/// kind of key (see KEY_), then for printable keys the character without and
+/// with shift, for unknown keys the scancode. Reads block until there is at
+/// least one event and return as many whole events as fit. Every open file has
+/// a queue of its own, which sees the events from the time it was opened.
+struct Keyboard
+{
+	listener: keyboard::Listener
+}
+
+pub const KEY_PRINTABLE: u8 = 0;
+pub const KEY_RETURN: u8 = 1;
+pub const KEY_BACKSPACE: u8 = 2;
+pub const KEY_SHIFT: u8 = 3;
+pub const KEY_ESCAPE: u8 = 4;
+pub const KEY_TAB: u8 = 5;
+pub const KEY_UNKNOWN: u8 = 6;
+
+const KEY_EVENT_SIZE: usize = 4;
+
+fn open_keyboard(flags: u32) -> Result<Box<File>, FsError>
+{
+	if flags & OPEN_WRITE != 0 { return Err(FsError::WrongMode) }
+	match keyboard::listen()
+	{
+		Some(listener) => Ok(Box::new(Keyboard { listener: listener }) as Box<File>),
+		None => Err(FsError::Busy),
+	}
+}
+
+fn encode_key_event(action: KeyboardAction, event: &mut [u8])
+{
+	let (pressed, key) = match action
+	{
+		KeyboardAction::KeyDown(key) => (1, key),
+		KeyboardAction::KeyUp(key) => (0, key),
+	};
+	let (kind, first, second) = match key
+	{
+		KeyboardKey::Printable(c, d) => (KEY_PRINTABLE, c as u8, d as u8),
+		KeyboardKey::Return => (KEY_RETURN, 0, 0),
+		KeyboardKey::Backspace => (KEY_BACKSPACE, 0, 0),
+		KeyboardKey::Shift => (KEY_SHIFT, 0, 0),
+		KeyboardKey::Escape => (KEY_ESCAPE, 0, 0),
+		KeyboardKey::Tab => (KEY_TAB, 0, 0),
+		KeyboardKey::Unknown(code) => (KEY_UNKNOWN, code, 0),
+	};
+	event[0] = pressed;
+	event[1] = kind;
+	event[2] = first;
+	event[3] = second;
+}
+
+impl File for Keyboard
+{
+	fn read(&mut self, buffer: &mut [u8]) -> Result<usize, FsError>
+	{
+		if buffer.len() < KEY_EVENT_SIZE { return Err(FsError::InvalidArgument) }
+
+		match self.listener.read_event()
+		{
+			Some(action) => encode_key_event(action, &mut buffer[.. KEY_EVENT_SIZE]),
+			None => return Err(FsError::Interrupted),
+		}
+		let mut count = KEY_EVENT_SIZE;
+		while count + KEY_EVENT_SIZE <= buffer.len()
+		{
+			match self.listener.try_read_event()
+			{
+				Some(action) => encode_key_event(action, &mut buffer[count .. count + KEY_EVENT_SIZE]),
+				None => break,
+			}
+			count += KEY_EVENT_SIZE;
+		}
+		Ok(count)
+	}
+
+	fn write(&mut self, _: &[u8]) -> Result<usize, FsError>
+	{
+		Err(FsError::WrongMode)
+	}
+
+	fn stat(&self) -> Stat
+	{
+		char_stat()
+	}
+}
+
+/// Output to the first serial port
+struct Serial
+{
+	flags: u32
+}
+
+fn open_serial(flags: u32) -> Result<Box<File>, FsError>
+{
+	serial::init();
+	Ok(Box::new(Serial { flags: flags }) as Box<File>)
+}
+
+impl File for Serial
+{
+	fn read(&mut self, _: &mut [u8]) -> Result<usize, FsError>
+	{
+		if self.flags & OPEN_READ == 0 { return Err(FsError::WrongMode) }
+		Err(FsError::Unsupported)
+	}
+
+	fn write(&mut self, buffer: &[u8]) -> Result<usize, FsError>
+	{
+		if self.flags & OPEN_WRITE == 0 { return Err(FsError::WrongMode) }
+		for &byte in buffer.iter()
+		{
+			serial::write_byte(byte);
+		}
+		Ok(buffer.len())
+	}
+
+	fn stat(&self) -> Stat
+	{
+		char_stat()
+	}
+}
+
+/// Reads nothing and swallows all writes
+struct Null
+{
+	flags: u32
+}
+
+fn open_null(flags: u32) -> Result<Box<File>, FsError>
+{
+	Ok(Box::new(Null { flags: flags }) as Box<File>)
+}
+
+impl File for Null
+{
+	fn read(&mut self, _: &mut [u8]) -> Result<usize, FsError>
+	{
+		if self.flags & OPEN_READ == 0 { return Err(FsError::WrongMode) }
+		Ok(0)
+	}
+
+	fn write(&mut self, buffer: &[u8]) -> Result<usize, FsError>
+	{
+		if self.flags & OPEN_WRITE == 0 { return Err(FsError::WrongMode) }
+		Ok(buffer.len())
+	}
+
+	fn stat(&self) -> Stat
+	{
+		char_stat()
+	}
+}
+
+/// Reads zeros and swallows all writes
+struct Zero
+{
+	flags: u32
+}
+
+fn open_zero(flags: u32) -> Result<Box<File>, FsError>
+{
+	Ok(Box::new(Zero { flags: flags }) as Box<File>)
+}
+
+impl File for Zero
+{
+	fn read(&mut self, buffer: &mut [u8]) -> Result<usize, FsError>
+	{
+		if self.flags & OPEN_READ == 0 { return Err(FsError::WrongMode) }
+		for byte in buffer.iter_mut() { *byte = 0; }
+		Ok(buffer.len())
+	}
+
+	fn write(&mut self, buffer: &[u8]) -> Result<usize, FsError>
+	{
+		if self.flags & OPEN_WRITE == 0 { return Err(FsError::WrongMode) }
+		Ok(buffer.len())
+	}
+
+	fn stat(&self) -> Stat
+	{
+		char_stat()
+	}
+}
+
+/// Pseudo-random bytes from an xorshift generator that is stirred with the
+/// timestamp counter on every read. Good enough to vary things, not for keys.
+struct Random
+{
+	flags: u32
+}
+
+// 0 means the generator has not been seeded yet
+static RANDOM_STATE: IrqLock<u64> = irq_lock!(0);
+
+fn open_random(flags: u32) -> Result<Box<File>, FsError>
+{
+	Ok(Box::new(Random { flags: flags }) as Box<File>)
+}
+
+impl File for Random
+{
+	fn read(&mut self, buffer: &mut [u8]) -> Result<usize, FsError>
+	{
+		if self.flags & OPEN_READ == 0 { return Err(FsError::WrongMode) }
+		let mut state = RANDOM_STATE.lock();
+		*state ^= cpu::timestamp();
+		if *state == 0 { *state = 0x9E3779B97F4A7C15; }
+
+		for chunk in buffer.chunks_mut(8)
+		{
+			*state ^= *state << 13;
+			*state ^= *state >> 7;
+			*state ^= *state << 17;
+			let mut value = *state;
+			for byte in chunk.iter_mut()
+			{
+				*byte = value as u8;
+				value >>= 8;
+			}
+		}
+		Ok(buffer.len())
+	}
+
+	/// Writing mixes the data into the state
+	fn write(&mut self, buffer: &[u8]) -> Result<usize, FsError>
+	{
+		if self.flags & OPEN_WRITE == 0 { return Err(FsError::WrongMode) }
+		let mut state = RANDOM_STATE.lock();
+		for (i, &byte) in buffer.iter().enumerate()
+		{
+			*state ^= (byte as u64) << (i % 8 * 8);
+		}
+		Ok(buffer.len())
+	}
+
+	fn stat(&self) -> Stat
+	{
+		char_stat()
+	}
+}
+
+/// A block device in memory that cannot be written, like a boot module
+struct MemoryDisk
+{
+	data: &'static [u8]
+}
+
+const MEMORY_DISK_BLOCK_SIZE: u32 = 512;
+
+impl BlockDevice for MemoryDisk
+{
+	fn block_size(&self) -> u32
+	{
+		MEMORY_DISK_BLOCK_SIZE
+	}
+
+	/// A partial block at the end counts as a whole one and reads as zeros
+	/// past the data
+	fn block_count(&self) -> u64
+	{
+		(self.data.len() as u64 + MEMORY_DISK_BLOCK_SIZE as u64 - 1) / MEMORY_DISK_BLOCK_SIZE as u64
+	}
+
+	fn read_blocks(&self, first: u64, buffer: &mut [u8]) -> Result<(), FsError>
+	{
+		let start = first * MEMORY_DISK_BLOCK_SIZE as u64;
+		if start + buffer.len() as u64 > self.block_count() * MEMORY_DISK_BLOCK_SIZE as u64 { return Err(FsError::InvalidArgument) }
+
+		for (i, byte) in buffer.iter_mut().enumerate()
+		{
+			let offset = start as usize + i;
+			*byte = if offset < self.data.len() { self.data[offset] } else { 0 };
+		}
+		Ok(())
+	}
+}
+
+/// Byte access to a block device, going through whole blocks
+struct BlockFile
+{
+	device: BlockDeviceRef,
+	inode: u64,
+	flags: u32,
+	position: u64
+}
+
+impl BlockFile
+{
+	fn size(&self) -> u64
+	{
+		self.device.block_count() * self.device.block_size() as u64
+	}
+}
+
+impl File for BlockFile
+{
+	fn read(&mut self, buffer: &mut [u8]) -> Result<usize, FsError>
+	{
+		if self.flags & OPEN_READ == 0 { return Err(FsError::WrongMode) }
+		let block_size = self.device.block_size() as u64;
+		let mut block: Vec<u8> = Vec::with_capacity(block_size as usize);
+		unsafe { block.set_len(block_size as usize); }
+
+		let mut done = 0;
+		while done < buffer.len() && self.position < self.size()
+		{
+			let start = (self.position % block_size) as usize;
+			try!(self.device.read_blocks(self.position / block_size, &mut block));
+			let length = if buffer.len() - done < block.len() - start { buffer.len() - done } else { block.len() - start };
+			for i in (0 .. length) { buffer[done + i] = block[start + i]; }
+			done += length;
+			self.position += length as u64;
+		}
+		Ok(done)
+	}
+
+	fn write(&mut self, buffer: &[u8]) -> Result<usize, FsError>
+	{
+		if self.flags & OPEN_WRITE == 0 { return Err(FsError::WrongMode) }
+		let block_size = self.device.block_size() as u64;
+		let mut block: Vec<u8> = Vec::with_capacity(block_size as usize);
+		unsafe { block.set_len(block_size as usize); }
+
+		let mut done = 0;
+		while done < buffer.len() && self.position < self.size()
+		{
+			let index = self.position / block_size;
+			let start = (self.position % block_size) as usize;
+			let length = if buffer.len() - done < block.len() - start { buffer.len() - done } else { block.len() - start };
+			// Blocks only partly written are read first
+			if length < block.len() { try!(self.device.read_blocks(index, &mut block)); }
+			for i in (0 .. length) { block[start + i] = buffer[done + i]; }
+			try!(self.device.write_blocks(index, &block));
+			done += length;
+			self.position += length as u64;
+		}
+		Ok(done)
+	}
+
+	fn stat(&self) -> Stat
+	{
+		Stat { inode: self.inode, kind: FileType::BlockDevice, mode: DEVICE_MODE, size: self.size() }
+	}
+
+	fn seek(&mut self, position: SeekFrom) -> Result<u64, FsError>
+	{
+		let (base, offset) = match position
+		{
+			SeekFrom::Start(offset) => (0, offset as i64),
+			SeekFrom::Current(offset) => (self.position, offset),
+			SeekFrom::End(offset) => (self.size(), offset),
+		};
+		if offset < 0 && (-offset) as u64 > base { return Err(FsError::InvalidArgument) }
+		self.position = if offset < 0 { base - (-offset) as u64 } else { base + offset as u64 };
+		Ok(self.position)
+	}
+}
@@ -0,0 +1,549 @@
+/*
+ * Virtual filesystem
+ *
+ * Every filesystem hands out inodes, which know their metadata and how to
+ * look up, list, read and write what they hold. Opening an inode gives a
+ * File, which keeps the position for reads and writes. Devices can supply a
+ * File of their own, everything else is read and written through its inode.
+ *
+ * Filesystems are mounted on directories of the tree. A path is resolved by
+ * first removing `.` and `..` from it, then finding the mount with the longest
+ * matching prefix and walking the rest of the path from the root of that
+ * filesystem. Symbolic links are not followed.
+ */
+
+use core::prelude::*;
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+use collections::string::String;
+use collections::vec::Vec;
+use platform::mmu::frame;
+use kernel::sync::{Mutex, Once};
+
+use self::devfs::DevFs;
+use self::tmpfs::TmpFs;
+
+pub mod console;
+pub mod devfs;
+pub mod tmpfs;
+
+pub type InodeRef = Arc<Box<Inode>>;
+pub type FileSystemRef = Arc<Box<FileSystem>>;
+
+/// Flags for `open`
+pub const OPEN_READ: u32 = 1 << 0;
+pub const OPEN_WRITE: u32 = 1 << 1;
+/// Creates a missing file
+pub const OPEN_CREATE: u32 = 1 << 2;
+/// Empties the file, needs OPEN_WRITE
+pub const OPEN_TRUNCATE: u32 = 1 << 3;
+/// Every write goes to the end of the file
+pub const OPEN_APPEND: u32 = 1 << 4;
+
+const DEFAULT_FILE_MODE: u32 = 0o644;
+
+/// Directories `init` mounts something on, with their modes. A read-only root
+/// has to provide them itself.
+pub const MOUNT_POINTS: &'static [(&'static str, u32)] = &[("tmp", 0o1777), ("dev", 0o755)];
+
+pub enum FsError
+{
+	NotFound,
+	NotDirectory,
+	IsDirectory,
+	ReadOnly,
+	/// The file was not opened for reading or for writing
+	WrongMode,
+	InvalidArgument,
+	/// Something is mounted on or below the path
+	Busy,
+	Unsupported,
+	Exists,
+	/// Removing a directory that still has entries
+	NotEmpty,
+	/// Renaming across filesystems
+	CrossDevice,
+	/// The calling thread was killed while waiting
+	Interrupted,
+	/// The filesystem is full
+	NoSpace,
+}
+
+impl Copy for FsError {}
+impl Clone for FsError { fn clone(&self) -> Self { *self } }
+
+impl FsError
+{
+	pub fn description(&self) -> &'static str
+	{
+		match *self
+		{
+			FsError::NotFound => "no such file or directory",
+			FsError::NotDirectory => "not a directory",
+			FsError::IsDirectory => "is a directory",
+			FsError::ReadOnly => "read-only filesystem",
+			FsError::WrongMode => "file not opened for this",
+			FsError::InvalidArgument => "invalid argument",
+			FsError::Busy => "mount point in the way",
+			FsError::Unsupported => "operation not supported",
+			FsError::Exists => "file exists",
+			FsError::NotEmpty => "directory not empty",
+			FsError::CrossDevice => "cannot move between filesystems",
+			FsError::Interrupted => "interrupted",
+			FsError::NoSpace => "no space left on device",
+		}
+	}
+}
+
+pub enum FileType
+{
+	File,
+	Directory,
+	Symlink,
+	CharDevice,
+	BlockDevice,
+}
+
+impl Copy for FileType {}
+impl Clone for FileType { fn clone(&self) -> Self { *self } }
+
+pub struct Stat
+{
+	/// Unique within its filesystem
+	pub inode: u64,
+	pub kind: FileType,
+	/// Permission bits
+	pub mode: u32,
+	pub size: u64
+}
+
+impl Copy for Stat {}
+impl Clone for Stat { fn clone(&self) -> Self { *self } }
+
+pub struct DirEntry
+{
+	pub name: String,
+	pub inode: u64,
+	pub kind: FileType
+}
+
+impl Clone for DirEntry
+{
+	fn clone(&self) -> DirEntry
+	{
+		DirEntry { name: self.name.clone(), inode: self.inode, kind: self.kind }
+	}
+}
+
+pub enum SeekFrom
+{
+	Start(u64),
+	Current(i64),
+	End(i64),
+}
+
+impl Copy for SeekFrom {}
+impl Clone for SeekFrom { fn clone(&self) -> Self { *self } }
+
+pub trait FileSystem: Send + Sync
+{
+	/// Type of the filesystem, like "initrd"
+	fn name(&self) -> &'static str;
+	fn root(&self) -> InodeRef;
+}
+
+/// A file, directory or device inside a filesystem. The defaults fit an inode
+/// that supports none of the operations.
+pub trait Inode: Send + Sync
+{
+	fn stat(&self) -> Stat;
+
+	/// Finds the entry `name` of a directory
+	fn lookup(&self, _name: &str) -> Result<InodeRef, FsError> { Err(FsError::NotDirectory) }
+
+	/// Every entry of a directory, without `.` and `..`
+	fn readdir(&self) -> Result<Vec<DirEntry>, FsError> { Err(FsError::NotDirectory) }
+
+	/// Returns the number of bytes read, 0 at the end of the file
+	fn read_at(&self, _offset: u64, _buffer: &mut [u8]) -> Result<usize, FsError> { Err(FsError::IsDirectory) }
+
+	/// Writing past the end extends the file, leaving a gap that reads as zeros
+	fn write_at(&self, _offset: u64, _buffer: &[u8]) -> Result<usize, FsError> { Err(FsError::ReadOnly) }
+
+	/// Cuts a file short or extends it with zeros
+	fn truncate(&self, _size: u64) -> Result<(), FsError> { Err(FsError::ReadOnly) }
+
+	/// Adds an empty file `name` to a directory
+	fn create(&self, _name: &str, _mode: u32) -> Result<InodeRef, FsError> { Err(FsError::ReadOnly) }
+
+	fn mkdir(&self, _name: &str, _mode: u32) -> Result<InodeRef, FsError> { Err(FsError::ReadOnly) }
+
+	/// Removes the entry `name` of a directory, which must not be a directory
+	fn unlink(&self, _name: &str) -> Result<(), FsError> { Err(FsError::ReadOnly) }
+
+	/// Removes the empty directory `name`
+	fn rmdir(&self, _name: &str) -> Result<(), FsError> { Err(FsError::ReadOnly) }
+
+	/// Moves the entry `name` to `new_name` in `new_parent`, replacing what is
+	/// there. The VFS only passes a `new_parent` of the same filesystem.
+	fn rename(&self, _name: &str, _new_parent: &Inode, _new_name: &str) -> Result<(), FsError> { Err(FsError::ReadOnly) }
+
+	/// Inodes that do not hold a range of bytes return their own File here
+	fn open(&self, _flags: u32) -> Option<Result<Box<File>, FsError>> { None }
+}
+
+/// An open file
+pub trait File: Send
+{
+	fn read(&mut self, buffer: &mut [u8]) -> Result<usize, FsError>;
+	fn write(&mut self, buffer: &[u8]) -> Result<usize, FsError>;
+	fn stat(&self) -> Stat;
+
+	/// Returns the new position
+	fn seek(&mut self, _position: SeekFrom) -> Result<u64, FsError> { Err(FsError::Unsupported) }
+
+	fn truncate(&mut self, _size: u64) -> Result<(), FsError> { Err(FsError::Unsupported) }
+
+	/// The next entry of a directory, None after the last one
+	fn readdir(&mut self) -> Result<Option<DirEntry>, FsError> { Err(FsError::NotDirectory) }
+}
+
+/// A File reading and writing through its inode at a position it keeps
+pub struct InodeFile
+{
+	inode: InodeRef,
+	flags: u32,
+	position: u64,
+	// Taken on the first readdir, so entries are not skipped or repeated when the directory changes
+	entries: Option<Vec<DirEntry>>
+}
+
+impl InodeFile
+{
+	pub fn new(inode: InodeRef, flags: u32) -> InodeFile
+	{
+		InodeFile { inode: inode, flags: flags, position: 0, entries: None }
+	}
+}
+
+impl File for InodeFile
+{
+	fn read(&mut self, buffer: &mut [u8]) -> Result<usize, FsError>
+	{
+		if self.flags & OPEN_READ == 0 { return Err(FsError::WrongMode) }
+		let count = try!(self.inode.read_at(self.position, buffer));
+		self.position += count as u64;
+		Ok(count)
+	}
+
+	fn write(&mut self, buffer: &[u8]) -> Result<usize, FsError>
+	{
+		if self.flags & OPEN_WRITE == 0 { return Err(FsError::WrongMode) }
+		if self.flags & OPEN_APPEND != 0 { self.position = self.inode.stat().size; }
+		let count = try!(self.inode.write_at(self.position, buffer));
+		self.position += count as u64;
+		Ok(count)
+	}
+
+	fn stat(&self) -> Stat
+	{
+		self.inode.stat()
+	}
+
+	fn seek(&mut self, position: SeekFrom) -> Result<u64, FsError>
+	{
+		let (base, offset) = match position
+		{
+			SeekFrom::Start(offset) => { self.position = offset; return Ok(offset) },
+			SeekFrom::Current(offset) => (self.position, offset),
+			SeekFrom::End(offset) => (self.inode.stat().size, offset),
+		};
+		if offset < 0 && (-offset) as u64 > base { return Err(FsError::InvalidArgument) }
+		self.position = if offset < 0 { base - (-offset) as u64 } else { base + offset as u64 };
+		Ok(self.position)
+	}
+
+	fn truncate(&mut self, size: u64) -> Result<(), FsError>
+	{
+		if self.flags & OPEN_WRITE == 0 { return Err(FsError::WrongMode) }
+		self.inode.truncate(size)
+	}
+
+	fn readdir(&mut self) -> Result<Option<DirEntry>, FsError>
+	{
+		if self.entries.is_none()
+		{
+			self.entries = Some(try!(self.inode.readdir()));
+		}
+		let entries = self.entries.as_ref().unwrap();
+		let entry = entries.get(self.position as usize).map(|entry| entry.clone());
+		if entry.is_some() { self.position += 1; }
+		Ok(entry)
+	}
+}
+
+/// Mounts a tmpfs at /tmp, and as the root when nothing else was mounted
+/// there, and the devices at /dev. Each tmpfs may fill half of the memory
+/// that is free at the time.
+pub fn init()
+{
+	if lookup("/").is_err()
+	{
+		let _ = mount("/", Box::new(TmpFs::new(tmpfs_pages())) as Box<FileSystem>);
+	}
+	for &(name, mode) in MOUNT_POINTS.iter()
+	{
+		let path = format!("/{}", name);
+		if lookup(&path).is_err()
+		{
+			let _ = mkdir(&path, mode);
+		}
+	}
+	if let Err(error) = mount("/tmp", Box::new(TmpFs::new(tmpfs_pages())) as Box<FileSystem>)
+	{
+		log!(Warning, "Could not mount /tmp: {}", error.description());
+	}
+
+	devfs::init();
+	if let Err(error) = mount("/dev", Box::new(DevFs) as Box<FileSystem>)
+	{
+		log!(Warning, "Could not mount /dev: {}", error.description());
+	}
+}
+
+fn tmpfs_pages() -> u64
+{
+	frame::free_count() as u64 / 2
+}
+
+struct Mount
+{
+	// The components of the mount point, empty for the root
+	path: Vec<String>,
+	filesystem: FileSystemRef
+}
+
+static MOUNTS: Once<Mutex<Vec<Mount>>> = once!();
+
+fn mounts_lock() -> &'static Mutex<Vec<Mount>>
+{
+	MOUNTS.call_once(|| Mutex::new(Vec::new()))
+}
+
+/// Attaches `filesystem` at `path`, which has to be an existing directory
+/// unless it is the root
+pub fn mount(path: &str, filesystem: Box<FileSystem>) -> Result<(), FsError>
+{
+	let components = try!(normalize(path));
+	if components.len() > 0
+	{
+		let inode = try!(lookup(path));
+		match inode.stat().kind
+		{
+			FileType::Directory => {},
+			_ => return Err(FsError::NotDirectory),
+		}
+	}
+
+	let mut mounts = mounts_lock().lock();
+	if mounts.iter().any(|mount| mount.path == components) { return Err(FsError::Busy) }
+	log!(Debug, "Mounted {} on /{}", filesystem.name(), components.connect("/"));
+	mounts.push(Mount { path: components, filesystem: Arc::new(filesystem) });
+	Ok(())
+}
+
+/// Detaches the filesystem at `path`. Files opened on it stay usable.
+pub fn unmount(path: &str) -> Result<(), FsError>
+{
+	let components = try!(normalize(path));
+	let mut mounts = mounts_lock().lock();
+	let index = match mounts.iter().position(|mount| mount.path == components)
+	{
+		Some(index) => index,
+		None => return Err(FsError::InvalidArgument),
+	};
+	if mounts.iter().any(|mount| mount.path.len() > components.len() && mount.path.starts_with(&components))
+	{
+		return Err(FsError::Busy);
+	}
+	mounts.remove(index);
+	Ok(())
+}
+
+/// Mount points and the type of filesystem mounted on them
+pub fn mounts() -> Vec<(String, &'static str)>
+{
+	mounts_lock().lock().iter().map(|mount|
+	{
+		let mut path = String::from_str("/");
+		path.push_str(&mount.path.connect("/"));
+		(path, mount.filesystem.name())
+	}).collect()
+}
+
+/// Splits an absolute path into its components, with `.` and `..` resolved.
+/// Relative paths are taken from the root, `..` of the root is the root.
+pub fn normalize(path: &str) -> Result<Vec<String>, FsError>
+{
+	if path.len() == 0 { return Err(FsError::NotFound) }
+
+	let mut components: Vec<String> = Vec::new();
+	for component in path.split('/')
+	{
+		match component
+		{
+			"" | "." => {},
+			".." => { components.pop(); },
+			name => components.push(String::from_str(name)),
+		}
+	}
+	Ok(components)
+}
+
+/// Finds the inode at `path`
+pub fn lookup(path: &str) -> Result<InodeRef, FsError>
+{
+	let components = try!(normalize(path));
+	let (_, inode) = try!(resolve(&components));
+	Ok(inode)
+}
+
+// Walks `components` from the root of the innermost mount they lie in
+fn resolve(components: &[String]) -> Result<(FileSystemRef, InodeRef), FsError>
+{
+	let (filesystem, depth) =
+	{
+		let mounts = mounts_lock().lock();
+		match mounts.iter().filter(|mount| components.starts_with(&mount.path)).max_by(|mount| mount.path.len())
+		{
+			Some(mount) => (mount.filesystem.clone(), mount.path.len()),
+			None => return Err(FsError::NotFound),
+		}
+	};
+
+	let mut inode = filesystem.root();
+	for component in components[depth ..].iter()
+	{
+		inode = try!(inode.lookup(component));
+	}
+	Ok((filesystem, inode))
+}
+
+// The directory holding the last component of `path` and that component
+fn resolve_parent(path: &str) -> Result<(FileSystemRef, InodeRef, String, Vec<String>), FsError>
+{
+	let mut components = try!(normalize(path));
+	let name = match components.pop()
+	{
+		Some(name) => name,
+		// The root has no parent
+		None => return Err(FsError::Busy),
+	};
+	let (filesystem, parent) = try!(resolve(&components));
+	components.push(name.clone());
+	Ok((filesystem, parent, name, components))
+}
+
+// Whether removing or moving the entry at `components` would pull a mount out from under the tree
+fn is_mount_point_or_above(components: &[String]) -> bool
+{
+	mounts_lock().lock().iter().any(|mount| mount.path.starts_with(components))
+}
+
+fn same_filesystem(a: &FileSystemRef, b: &FileSystemRef) -> bool
+{
+	&**a as *const Box<FileSystem> == &**b as *const Box<FileSystem>
+}
+
+/// Opens the file at `path`, with OPEN_READ, OPEN_WRITE or both in `flags`
+/// and any of OPEN_CREATE, OPEN_TRUNCATE and OPEN_APPEND
+pub fn open(path: &str, flags: u32) -> Result<Box<File>, FsError>
+{
+	if flags & (OPEN_READ | OPEN_WRITE) == 0 { return Err(FsError::InvalidArgument) }
+
+	let inode = match lookup(path)
+	{
+		Ok(inode) => inode,
+		Err(FsError::NotFound) if flags & OPEN_CREATE != 0 =>
+		{
+			let (_, parent, name, _) = try!(resolve_parent(path));
+			try!(parent.create(&name, DEFAULT_FILE_MODE))
+		},
+		Err(error) => return Err(error),
+	};
+	if let Some(result) = inode.open(flags)
+	{
+		return result;
+	}
+	if let FileType::Directory = inode.stat().kind
+	{
+		if flags & OPEN_WRITE != 0 { return Err(FsError::IsDirectory) }
+	}
+	if flags & OPEN_TRUNCATE != 0
+	{
+		if flags & OPEN_WRITE == 0 { return Err(FsError::InvalidArgument) }
+		try!(inode.truncate(0));
+	}
+	Ok(Box::new(InodeFile::new(inode, flags)) as Box<File>)
+}
+
+pub fn mkdir(path: &str, mode: u32) -> Result<(), FsError>
+{
+	let (_, parent, name, _) = try!(resolve_parent(path));
+	try!(parent.mkdir(&name, mode));
+	Ok(())
+}
+
+pub fn unlink(path: &str) -> Result<(), FsError>
+{
+	let (_, parent, name, components) = try!(resolve_parent(path));
+	if is_mount_point_or_above(&components) { return Err(FsError::Busy) }
+	parent.unlink(&name)
+}
+
+pub fn rmdir(path: &str) -> Result<(), FsError>
+{
+	let (_, parent, name, components) = try!(resolve_parent(path));
+	if is_mount_point_or_above(&components) { return Err(FsError::Busy) }
+	parent.rmdir(&name)
+}
+
+/// Moves `from` to `to` within one filesystem, replacing what is at `to`
+pub fn rename(from: &str, to: &str) -> Result<(), FsError>
+{
+	let (from_filesystem, from_parent, from_name, from_components) = try!(resolve_parent(from));
+	let (to_filesystem, to_parent, to_name, to_components) = try!(resolve_parent(to));
+	if is_mount_point_or_above(&from_components) || is_mount_point_or_above(&to_components) { return Err(FsError::Busy) }
+	if !same_filesystem(&from_filesystem, &to_filesystem) { return Err(FsError::CrossDevice) }
+	from_parent.rename(&from_name, &**to_parent, &to_name)
+}
+
+pub fn truncate(path: &str, size: u64) -> Result<(), FsError>
+{
+	try!(lookup(path)).truncate(size)
+}
+
+pub fn stat(path: &str) -> Result<Stat, FsError>
+{
+	Ok(try!(lookup(path)).stat())
+}
+
+pub fn readdir(path: &str) -> Result<Vec<DirEntry>, FsError>
+{
+	try!(lookup(path)).readdir()
+}
+
+/// The whole contents of the file at `path`
+pub fn read_file(path: &str) -> Result<Vec<u8>, FsError>
+{
+	let inode = try!(lookup(path));
+	let mut data = Vec::with_capacity(inode.stat().size as usize);
+	let mut buffer = [0u8; 512];
+	loop
+	{
+		let count = try!(inode.read_at(data.len() as u64, &mut buffer));
+		if count == 0 { break }
+		data.push_all(&buffer[.. count]);
+	}
+	Ok(data)
+}
@@ -0,0 +1,457 @@
+/*
+ * Writable filesystem in memory
+ *
+ * All nodes of one tmpfs live in a table indexed by inode number, behind one
+ * lock. File contents are kept in pages that are only allocated when written,
+ * so writing far past the end of a file leaves a hole that costs nothing and
+ * reads as zeros. The pages of all files together are limited to the size
+ * given to TmpFs::new, past it writes fail with FsError::NoSpace.
+ *
+ * A node stays in the table while it has a directory entry or an open inode,
+ * so a file that is removed while open can still be read and written.
+ */
+
+use core::prelude::*;
+use core::iter;
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+use collections::BTreeMap;
+use collections::string::String;
+use collections::vec::Vec;
+use kernel::fs::{DirEntry, FileSystem, FileType, FsError, Inode, InodeRef, Stat};
+use kernel::sync::Mutex;
+
+const PAGE_SIZE: u64 = 4096;
+const ROOT_INODE: u64 = 1;
+const ROOT_MODE: u32 = 0o755;
+
+enum Contents
+{
+	File { size: u64, pages: BTreeMap<u64, Vec<u8>> },
+	Directory { parent: u64, entries: BTreeMap<String, u64> },
+}
+
+struct Node
+{
+	mode: u32,
+	contents: Contents,
+	/// In a directory, or the root
+	linked: bool,
+	/// Number of TmpInodes referring to the node
+	handles: usize
+}
+
+impl Node
+{
+	fn file_type(&self) -> FileType
+	{
+		match self.contents
+		{
+			Contents::File { .. } => FileType::File,
+			Contents::Directory { .. } => FileType::Directory,
+		}
+	}
+}
+
+struct State
+{
+	nodes: BTreeMap<u64, Node>,
+	next_inode: u64,
+	/// File pages allocated, and how many may be
+	used_pages: u64,
+	page_limit: u64
+}
+
+pub struct TmpFs
+{
+	state: Arc<Mutex<State>>
+}
+
+impl TmpFs
+{
+	/// An empty tmpfs holding at most `page_limit` pages of file contents
+	pub fn new(page_limit: u64) -> TmpFs
+	{
+		let mut nodes = BTreeMap::new();
+		nodes.insert(ROOT_INODE, Node
+		{
+			mode: ROOT_MODE,
+			contents: Contents::Directory { parent: ROOT_INODE, entries: BTreeMap::new() },
+			linked: true,
+			handles: 0
+		});
+		TmpFs { state: Arc::new(Mutex::new(State
+		{
+			nodes: nodes,
+			next_inode: ROOT_INODE + 1,
+			used_pages: 0,
+			page_limit: page_limit
+		})) }
+	}
+}
+
+impl FileSystem for TmpFs
+{
+	fn name(&self) -> &'static str
+	{
+		"tmpfs"
+	}
+
+	fn root(&self) -> InodeRef
+	{
+		let mut state = self.state.lock();
+		handle(&self.state, &mut state, ROOT_INODE)
+	}
+}
+
+struct TmpInode
+{
+	state: Arc<Mutex<State>>,
+	inode: u64
+}
+
+// A new inode for an existing node, `state` being the locked `shared`
+fn handle(shared: &Arc<Mutex<State>>, state: &mut State, inode: u64) -> InodeRef
+{
+	if let Some(node) = state.nodes.get_mut(&inode)
+	{
+		node.handles += 1;
+	}
+	Arc::new(Box::new(TmpInode { state: shared.clone(), inode: inode }) as Box<Inode>)
+}
+
+impl Drop for TmpInode
+{
+	fn drop(&mut self)
+	{
+		let mut state = self.state.lock();
+		let unused = match state.nodes.get_mut(&self.inode)
+		{
+			Some(node) => { node.handles -= 1; node.handles == 0 && !node.linked },
+			None => false,
+		};
+		if unused
+		{
+			state.drop_node(self.inode);
+		}
+	}
+}
+
+impl State
+{
+	fn node(&self, inode: u64) -> &Node
+	{
+		match self.nodes.get(&inode)
+		{
+			Some(node) => node,
+			None => panic!("tmpfs inode {} is missing", inode),
+		}
+	}
+
+	fn node_mut(&mut self, inode: u64) -> &mut Node
+	{
+		match self.nodes.get_mut(&inode)
+		{
+			Some(node) => node,
+			None => panic!("tmpfs inode {} is missing", inode),
+		}
+	}
+
+	fn entries(&self, directory: u64) -> Result<&BTreeMap<String, u64>, FsError>
+	{
+		let node = self.node(directory);
+		match node.contents
+		{
+			// Entries cannot be added to a directory that was removed while open
+			Contents::Directory { ref entries, .. } if node.linked => Ok(entries),
+			Contents::Directory { .. } => Err(FsError::NotFound),
+			Contents::File { .. } => Err(FsError::NotDirectory),
+		}
+	}
+
+	fn entries_mut(&mut self, directory: u64) -> Result<&mut BTreeMap<String, u64>, FsError>
+	{
+		try!(self.entries(directory));
+		match self.node_mut(directory).contents
+		{
+			Contents::Directory { ref mut entries, .. } => Ok(entries),
+			Contents::File { .. } => Err(FsError::NotDirectory),
+		}
+	}
+
+	fn entry(&self, directory: u64, name: &str) -> Result<u64, FsError>
+	{
+		match try!(self.entries(directory)).get(name)
+		{
+			Some(&inode) => Ok(inode),
+			None => Err(FsError::NotFound),
+		}
+	}
+
+	// Adds a node under `name` in `directory`, which must not have that entry yet
+	fn add(&mut self, directory: u64, name: &str, mode: u32, contents: Contents) -> Result<u64, FsError>
+	{
+		if name.len() == 0 || name.contains("/") { return Err(FsError::InvalidArgument) }
+		if try!(self.entries(directory)).contains_key(name) { return Err(FsError::Exists) }
+
+		let inode = self.next_inode;
+		self.next_inode += 1;
+		self.nodes.insert(inode, Node { mode: mode, contents: contents, linked: true, handles: 0 });
+		try!(self.entries_mut(directory)).insert(String::from_str(name), inode);
+		Ok(inode)
+	}
+
+	// Takes `name` out of `directory` and drops the node unless it is open
+	fn remove(&mut self, directory: u64, name: &str)
+	{
+		let inode = match self.entries_mut(directory)
+		{
+			Ok(entries) => entries.remove(name),
+			Err(_) => None,
+		};
+		if let Some(inode) = inode
+		{
+			let unused =
+			{
+				let node = self.node_mut(inode);
+				node.linked = false;
+				node.handles == 0
+			};
+			if unused { self.drop_node(inode); }
+		}
+	}
+
+	// Removes the node from the table and gives back its pages
+	fn drop_node(&mut self, inode: u64)
+	{
+		if let Some(node) = self.nodes.remove(&inode)
+		{
+			if let Contents::File { ref pages, .. } = node.contents
+			{
+				self.used_pages -= pages.len() as u64;
+			}
+		}
+	}
+
+	fn is_empty_directory(&self, inode: u64) -> bool
+	{
+		match self.node(inode).contents
+		{
+			Contents::Directory { ref entries, .. } => entries.is_empty(),
+			Contents::File { .. } => false,
+		}
+	}
+
+	// Whether `inode` is `ancestor` or lies below it
+	fn is_below(&self, mut inode: u64, ancestor: u64) -> bool
+	{
+		loop
+		{
+			if inode == ancestor { return true }
+			if inode == ROOT_INODE { return false }
+			inode = match self.node(inode).contents
+			{
+				Contents::Directory { parent, .. } => parent,
+				Contents::File { .. } => return false,
+			};
+		}
+	}
+}
+
+impl Inode for TmpInode
+{
+	fn stat(&self) -> Stat
+	{
+		let state = self.state.lock();
+		let node = state.node(self.inode);
+		let size = match node.contents
+		{
+			Contents::File { size, .. } => size,
+			Contents::Directory { ref entries, .. } => entries.len() as u64,
+		};
+		Stat { inode: self.inode, kind: node.file_type(), mode: node.mode, size: size }
+	}
+
+	fn lookup(&self, name: &str) -> Result<InodeRef, FsError>
+	{
+		let mut state = self.state.lock();
+		let inode = try!(state.entry(self.inode, name));
+		Ok(handle(&self.state, &mut state, inode))
+	}
+
+	fn readdir(&self) -> Result<Vec<DirEntry>, FsError>
+	{
+		let state = self.state.lock();
+		let entries = try!(state.entries(self.inode));
+		Ok(entries.iter().map(|(name, &inode)| DirEntry
+		{
+			name: name.clone(),
+			inode: inode,
+			kind: state.node(inode).file_type()
+		}).collect())
+	}
+
+	fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError>
+	{
+		let state = self.state.lock();
+		let (size, pages) = match state.node(self.inode).contents
+		{
+			Contents::File { size, ref pages } => (size, pages),
+			Contents::Directory { .. } => return Err(FsError::IsDirectory),
+		};
+		if offset >= size { return Ok(0) }
+
+		let count = if size - offset < buffer.len() as u64 { (size - offset) as usize } else { buffer.len() };
+		let mut done = 0;
+		while done < count
+		{
+			let position = offset + done as u64;
+			let start = (position % PAGE_SIZE) as usize;
+			let length = if count - done < PAGE_SIZE as usize - start { count - done } else { PAGE_SIZE as usize - start };
+			match pages.get(&(position / PAGE_SIZE))
+			{
+				Some(page) => for i in (0 .. length) { buffer[done + i] = page[start + i]; },
+				None => for i in (0 .. length) { buffer[done + i] = 0; },
+			}
+			done += length;
+		}
+		Ok(count)
+	}
+
+	fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize, FsError>
+	{
+		let mut state = self.state.lock();
+		let state = &mut *state;
+		let (size, pages) = match state.nodes.get_mut(&self.inode)
+		{
+			Some(&mut Node { contents: Contents::File { ref mut size, ref mut pages }, .. }) => (size, pages),
+			Some(_) => return Err(FsError::IsDirectory),
+			None => panic!("tmpfs inode {} is missing", self.inode),
+		};
+
+		let mut done = 0;
+		while done < buffer.len()
+		{
+			let position = offset + done as u64;
+			let start = (position % PAGE_SIZE) as usize;
+			let length = if buffer.len() - done < PAGE_SIZE as usize - start { buffer.len() - done } else { PAGE_SIZE as usize - start };
+			let index = position / PAGE_SIZE;
+			if !pages.contains_key(&index)
+			{
+				// A short write when some of the buffer fit
+				if state.used_pages >= state.page_limit
+				{
+					if done == 0 { return Err(FsError::NoSpace) }
+					break;
+				}
+				pages.insert(index, iter::repeat(0u8).take(PAGE_SIZE as usize).collect());
+				state.used_pages += 1;
+			}
+			let page = pages.get_mut(&index).unwrap();
+			for i in (0 .. length) { page[start + i] = buffer[done + i]; }
+			done += length;
+		}
+		if offset + done as u64 > *size { *size = offset + done as u64; }
+		Ok(done)
+	}
+
+	fn truncate(&self, new_size: u64) -> Result<(), FsError>
+	{
+		let mut state = self.state.lock();
+		let state = &mut *state;
+		let (size, pages) = match state.nodes.get_mut(&self.inode)
+		{
+			Some(&mut Node { contents: Contents::File { ref mut size, ref mut pages }, .. }) => (size, pages),
+			Some(_) => return Err(FsError::IsDirectory),
+			None => panic!("tmpfs inode {} is missing", self.inode),
+		};
+
+		// Pages past the new end go, and the rest of the last one is cleared so
+		// growing the file again shows zeros
+		let first_unused = (new_size + PAGE_SIZE - 1) / PAGE_SIZE;
+		let unused: Vec<u64> = pages.keys().filter(|&&index| index >= first_unused).map(|&index| index).collect();
+		for index in unused.iter()
+		{
+			pages.remove(index);
+		}
+		state.used_pages -= unused.len() as u64;
+		if let Some(page) = pages.get_mut(&(new_size / PAGE_SIZE))
+		{
+			for i in ((new_size % PAGE_SIZE) as usize .. PAGE_SIZE as usize) { page[i] = 0; }
+		}
+		*size = new_size;
+		Ok(())
+	}
+
+	fn create(&self, name: &str, mode: u32) -> Result<InodeRef, FsError>
+	{
+		let mut state = self.state.lock();
+		let inode = try!(state.add(self.inode, name, mode, Contents::File { size: 0, pages: BTreeMap::new() }));
+		Ok(handle(&self.state, &mut state, inode))
+	}
+
+	fn mkdir(&self, name: &str, mode: u32) -> Result<InodeRef, FsError>
+	{
+		let mut state = self.state.lock();
+		let inode = try!(state.add(self.inode, name, mode, Contents::Directory { parent: self.inode, entries: BTreeMap::new() }));
+		Ok(handle(&self.state, &mut state, inode))
+	}
+
+	fn unlink(&self, name: &str) -> Result<(), FsError>
+	{
+		let mut state = self.state.lock();
+		let inode = try!(state.entry(self.inode, name));
+		if let FileType::Directory = state.node(inode).file_type() { return Err(FsError::IsDirectory) }
+		state.remove(self.inode, name);
+		Ok(())
+	}
+
+	fn rmdir(&self, name: &str) -> Result<(), FsError>
+	{
+		let mut state = self.state.lock();
+		let inode = try!(state.entry(self.inode, name));
+		if let FileType::File = state.node(inode).file_type() { return Err(FsError::NotDirectory) }
+		if !state.is_empty_directory(inode) { return Err(FsError::NotEmpty) }
+		state.remove(self.inode, name);
+		Ok(())
+	}
+
+	fn rename(&self, name: &str, new_parent: &Inode, new_name: &str) -> Result<(), FsError>
+	{
+		if new_name.len() == 0 || new_name.contains("/") { return Err(FsError::InvalidArgument) }
+		// Stat before locking, new_parent is an inode of this filesystem and takes the same lock
+		let target_directory = new_parent.stat().inode;
+
+		let mut state = self.state.lock();
+		let inode = try!(state.entry(self.inode, name));
+		try!(state.entries(target_directory));
+
+		let moving_directory = match state.node(inode).file_type() { FileType::Directory => true, _ => false };
+		if moving_directory && state.is_below(target_directory, inode) { return Err(FsError::InvalidArgument) }
+
+		match state.entry(target_directory, new_name)
+		{
+			Ok(existing) if existing == inode => return Ok(()),
+			Ok(existing) =>
+			{
+				match (moving_directory, state.node(existing).file_type())
+				{
+					(true, FileType::Directory) => if !state.is_empty_directory(existing) { return Err(FsError::NotEmpty) },
+					(true, _) => return Err(FsError::NotDirectory),
+					(false, FileType::Directory) => return Err(FsError::IsDirectory),
+					(false, _) => {},
+				}
+				state.remove(target_directory, new_name);
+			},
+			Err(_) => {},
+		}
+
+		try!(state.entries_mut(self.inode)).remove(name);
+		try!(state.entries_mut(target_directory)).insert(String::from_str(new_name), inode);
+		if let Contents::Directory { ref mut parent, .. } = state.node_mut(inode).contents
+		{
+			*parent = target_directory;
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,378 @@
+/*
+ * Kernel heap
+ *
+ * A first-fit allocator over an address-ordered list of free blocks. The heap
+ * lives at HEAP_START and grows upwards whenever no free block is large enough.
+ * Frames are reserved when the heap grows and mapped lazily by the page fault
+ * handler, so running out of memory makes an allocation fail rather than a
+ * later page fault. Adjacent free blocks are merged on release.
+ */
+
+use core::prelude::*;
+use core::{mem, ptr};
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use platform::mmu::{frame, paging};
+use kernel::sync::IrqLock;
+
+pub const HEAP_START: u32 = 0xD0000000;
+pub const HEAP_MAX: u32 = 0xE0000000;
+
+const INITIAL_PAGES: u32 = 16;
+const MIN_BLOCK: usize = 8;
+
+struct FreeBlock
+{
+	size: usize,
+	next: *mut FreeBlock
+}
+
+pub struct HeapStats
+{
+	pub heap_size: usize,
+	pub used: usize,
+	pub free: usize,
+	pub free_blocks: usize,
+	pub largest_free: usize,
+	pub allocations: usize
+}
+
+impl Copy for HeapStats {}
+impl Clone for HeapStats { fn clone(&self) -> Self { *self } }
+
+impl HeapStats
+{
+	/// Share of free memory that is not part of the largest free block, in percent
+	pub fn fragmentation(&self) -> usize
+	{
+		if self.free == 0 { 0 } else { 100 - self.largest_free * 100 / self.free }
+	}
+}
+
+struct Heap
+{
+	free_list: *mut FreeBlock,
+	used_bytes: usize,
+	allocation_count: usize
+}
+
+unsafe impl Send for Heap {}
+
+static HEAP: IrqLock<Heap> = irq_lock!(Heap { free_list: 0 as *mut FreeBlock, used_bytes: 0, allocation_count: 0 });
+
+// Kept outside the lock, the page fault handler reads it while the heap may be locked
+static HEAP_TOP: AtomicUsize = ATOMIC_USIZE_INIT;
+
+pub fn init()
+{
+	HEAP_TOP.store(HEAP_START as usize, Ordering::SeqCst);
+	// Every address space shares these tables, so they are created now
+	paging::reserve_tables(HEAP_START, HEAP_MAX);
+	if !HEAP.lock().grow(INITIAL_PAGES * paging::PAGE_SIZE)
+	{
+		panic!("Unable to set up the kernel heap");
+	}
+}
+
+/// Allocates `size` bytes aligned to `align`. Returns null when out of memory.
+pub fn allocate(size: usize, align: usize) -> *mut u8
+{
+	let size = usable_size(size, align);
+	let align = if align < MIN_BLOCK { MIN_BLOCK } else { align };
+
+	let mut heap = HEAP.lock();
+	loop
+	{
+		let result = heap.take_block(size, align);
+		if !result.is_null()
+		{
+			heap.used_bytes += size;
+			heap.allocation_count += 1;
+			return result;
+		}
+		if !heap.grow((size + align) as u32)
+		{
+			return ptr::null_mut();
+		}
+	}
+}
+
+/// Releases memory obtained from `allocate` with the same size
+pub fn deallocate(pointer: *mut u8, size: usize)
+{
+	if pointer.is_null() { return }
+
+	let size = usable_size(size, MIN_BLOCK);
+	let mut heap = HEAP.lock();
+	heap.used_bytes -= size;
+	heap.allocation_count -= 1;
+	heap.insert_block(pointer as u32, size);
+}
+
+pub fn reallocate(pointer: *mut u8, old_size: usize, size: usize, align: usize) -> *mut u8
+{
+	let old_usable = usable_size(old_size, align);
+	let new_usable = usable_size(size, align);
+	if new_usable <= old_usable && pointer as usize % align == 0
+	{
+		// Shrink in place and hand the tail back to the free list
+		if old_usable - new_usable >= mem::size_of::<FreeBlock>()
+		{
+			let mut heap = HEAP.lock();
+			heap.used_bytes -= old_usable - new_usable;
+			heap.insert_block(pointer as u32 + new_usable as u32, old_usable - new_usable);
+			return pointer;
+		}
+		if new_usable == old_usable { return pointer }
+	}
+
+	let result = allocate(size, align);
+	if !result.is_null()
+	{
+		unsafe { ptr::copy_nonoverlapping(pointer, result, if old_size < size { old_size } else { size }); }
+		deallocate(pointer, old_size);
+	}
+	result
+}
+
+pub fn usable_size(size: usize, _align: usize) -> usize
+{
+	round_up(if size < MIN_BLOCK { MIN_BLOCK } else { size }, MIN_BLOCK)
+}
+
+/// C-style allocation that remembers its own size
+pub fn kmalloc(size: usize) -> *mut u8
+{
+	let total = size + MIN_BLOCK;
+	let block = allocate(total, MIN_BLOCK);
+	if block.is_null() { return block }
+	unsafe
+	{
+		*(block as *mut usize) = total;
+		block.offset(MIN_BLOCK as isize)
+	}
+}
+
+/// Releases memory obtained from `kmalloc`
+pub fn kfree(pointer: *mut u8)
+{
+	if pointer.is_null() { return }
+	unsafe
+	{
+		let block = pointer.offset(-(MIN_BLOCK as isize));
+		deallocate(block, *(block as *const usize));
+	}
+}
+
+pub fn stats() -> HeapStats
+{
+	let mut free = 0;
+	let mut free_blocks = 0;
+	let mut largest_free = 0;
+
+	let heap = HEAP.lock();
+	let mut block = heap.free_list;
+	while !block.is_null()
+	{
+		let size = unsafe { (*block).size };
+		free += size;
+		free_blocks += 1;
+		if size > largest_free { largest_free = size; }
+		block = unsafe { (*block).next };
+	}
+
+	HeapStats
+	{
+		heap_size: HEAP_TOP.load(Ordering::SeqCst) - HEAP_START as usize,
+		used: heap.used_bytes,
+		free: free,
+		free_blocks: free_blocks,
+		largest_free: largest_free,
+		allocations: heap.allocation_count
+	}
+}
+
+/// Whether `address` lies in the part of the heap that has been handed out
+pub fn contains(address: u32) -> bool
+{
+	address >= HEAP_START && (address as usize) < HEAP_TOP.load(Ordering::SeqCst)
+}
+
+/// Backs [pointer, pointer + size) with frames right away instead of on the
+/// first touch, for memory that must never fault, like kernel stacks. The
+/// frames were reserved when the heap grew.
+pub fn populate(pointer: *mut u8, size: usize) -> bool
+{
+	// Keeps interrupts off, so nothing else maps these pages in the meantime
+	let _heap = HEAP.lock();
+	let mut page = pointer as u32 & !(paging::PAGE_SIZE - 1);
+	let end = pointer as u32 + size as u32;
+	while page < end
+	{
+		if paging::translate(page).is_none()
+		{
+			let phys = match frame::alloc_reserved_frame() { Some(phys) => phys, None => return false };
+			paging::map(page, phys, paging::WRITABLE);
+			unsafe { ptr::write_bytes(page as *mut u8, 0, paging::PAGE_SIZE as usize); }
+		}
+		page += paging::PAGE_SIZE;
+	}
+	true
+}
+
+impl Heap
+{
+	/// Extends the heap by at least `bytes` and frees the new space. A frame is
+	/// reserved for every new page, but only mapped once the page fault handler
+	/// sees the page being touched.
+	fn grow(&mut self, bytes: u32) -> bool
+	{
+		let pages = (bytes + paging::PAGE_SIZE - 1) / paging::PAGE_SIZE;
+		let start = HEAP_TOP.load(Ordering::SeqCst) as u32;
+		if HEAP_MAX - start < pages * paging::PAGE_SIZE { return false }
+		if !frame::reserve_frames(pages) { return false }
+
+		let size = pages * paging::PAGE_SIZE;
+		HEAP_TOP.store((start + size) as usize, Ordering::SeqCst);
+		self.insert_block(start, size as usize);
+		true
+	}
+
+	/// Carves a block out of the first free block that fits
+	fn take_block(&mut self, size: usize, align: usize) -> *mut u8
+	{
+		let mut previous: *mut FreeBlock = ptr::null_mut();
+		let mut current = self.free_list;
+
+		unsafe
+		{
+			while !current.is_null()
+			{
+				let start = current as usize;
+				let mut aligned = round_up(start, align);
+				// A leading remainder has to be able to hold a free block header
+				if aligned != start && aligned - start < mem::size_of::<FreeBlock>()
+				{
+					aligned = round_up(start + mem::size_of::<FreeBlock>(), align);
+				}
+
+				let front = aligned - start;
+				let block_size = (*current).size;
+				if front + size <= block_size
+				{
+					let next = (*current).next;
+					let back = block_size - front - size;
+
+					let mut link = next;
+					if back >= mem::size_of::<FreeBlock>()
+					{
+						let tail = (aligned + size) as *mut FreeBlock;
+						(*tail).size = back;
+						(*tail).next = next;
+						link = tail;
+					}
+
+					if front > 0
+					{
+						(*current).size = front;
+						(*current).next = link;
+					}
+					else if previous.is_null()
+					{
+						self.free_list = link;
+					}
+					else
+					{
+						(*previous).next = link;
+					}
+
+					return aligned as *mut u8;
+				}
+
+				previous = current;
+				current = (*current).next;
+			}
+		}
+		ptr::null_mut()
+	}
+
+	/// Puts [address, address + size) back into the free list, merging neighbours
+	fn insert_block(&mut self, address: u32, size: usize)
+	{
+		let block = address as *mut FreeBlock;
+		let mut previous: *mut FreeBlock = ptr::null_mut();
+		let mut current = self.free_list;
+
+		unsafe
+		{
+			while !current.is_null() && (current as u32) < address
+			{
+				previous = current;
+				current = (*current).next;
+			}
+
+			(*block).size = size;
+			(*block).next = current;
+
+			if !current.is_null() && address as usize + size == current as usize
+			{
+				(*block).size += (*current).size;
+				(*block).next = (*current).next;
+			}
+
+			if previous.is_null()
+			{
+				self.free_list = block;
+			}
+			else if previous as usize + (*previous).size == address as usize
+			{
+				(*previous).size += (*block).size;
+				(*previous).next = (*block).next;
+			}
+			else
+			{
+				(*previous).next = block;
+			}
+		}
+	}
+}
+
+fn round_up(value: usize, align: usize) -> usize
+{
+	(value + align - 1) / align * align
+}
+
+// Hooks used by liballoc when it is built with the external_funcs feature
+
+#[no_mangle]
+pub extern "C" fn rust_allocate(size: usize, align: usize) -> *mut u8
+{
+	allocate(size, align)
+}
+
+#[no_mangle]
+pub extern "C" fn rust_deallocate(pointer: *mut u8, old_size: usize, _align: usize)
+{
+	deallocate(pointer, old_size)
+}
+
+#[no_mangle]
+pub extern "C" fn rust_reallocate(pointer: *mut u8, old_size: usize, size: usize, align: usize) -> *mut u8
+{
+	reallocate(pointer, old_size, size, align)
+}
+
+#[no_mangle]
+pub extern "C" fn rust_reallocate_inplace(_pointer: *mut u8, old_size: usize, _size: usize, align: usize) -> usize
+{
+	// Blocks are never resized in place, so this only succeeds when nothing changes
+	usable_size(old_size, align)
+}
+
+#[no_mangle]
+pub extern "C" fn rust_usable_size(size: usize, align: usize) -> usize
+{
+	usable_size(size, align)
+}
+
+#[no_mangle]
+pub extern "C" fn rust_stats_print() {}
@@ -0,0 +1,314 @@
+/*
+ * Initial ramdisk
+ *
+ * The first boot module holding a USTAR archive is mounted as the read-only
+ * root filesystem. Its entries are turned into a tree of nodes once at boot,
+ * file contents are not copied but point into the module, which stays mapped.
+ *
+ * Directories missing from the archive are created on the way to the entries
+ * inside them, and so are the mount points listed in fs::MOUNT_POINTS.
+ * Symbolic links are kept but not followed.
+ */
+
+use core::prelude::*;
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+use collections::string::String;
+use collections::vec::Vec;
+use kernel::boot;
+use kernel::fs;
+use kernel::fs::{DirEntry, FileSystem, FileType, FsError, Inode, InodeRef, Stat};
+use kernel::sync::Once;
+
+use self::tar::EntryKind;
+
+pub mod tar;
+
+const DEFAULT_DIRECTORY_MODE: u32 = 0o755;
+
+pub enum NodeKind
+{
+	File(&'static [u8]),
+	Directory(Vec<Node>),
+	Symlink(String),
+}
+
+pub struct Node
+{
+	pub name: String,
+	/// Permission bits from the archive
+	pub mode: u32,
+	pub kind: NodeKind
+}
+
+impl Node
+{
+	fn directory(name: &str, mode: u32) -> Node
+	{
+		Node { name: String::from_str(name), mode: mode, kind: NodeKind::Directory(Vec::new()) }
+	}
+
+	pub fn is_directory(&self) -> bool
+	{
+		match self.kind { NodeKind::Directory(_) => true, _ => false }
+	}
+
+	/// Length of the contents of a file or of the target of a link
+	pub fn size(&self) -> usize
+	{
+		match self.kind
+		{
+			NodeKind::File(data) => data.len(),
+			NodeKind::Directory(_) => 0,
+			NodeKind::Symlink(ref target) => target.len(),
+		}
+	}
+
+	/// The contents of a file, None for anything else
+	pub fn data(&self) -> Option<&'static [u8]>
+	{
+		match self.kind { NodeKind::File(data) => Some(data), _ => None }
+	}
+
+	/// The entries of a directory, empty for anything else
+	pub fn children(&self) -> &[Node]
+	{
+		match self.kind { NodeKind::Directory(ref children) => children, _ => &[] }
+	}
+
+	pub fn child(&self, name: &str) -> Option<&Node>
+	{
+		self.children().iter().find(|node| node.name == name)
+	}
+
+	fn child_mut(&mut self, name: &str) -> Option<&mut Node>
+	{
+		match self.kind
+		{
+			NodeKind::Directory(ref mut children) => children.iter_mut().find(|node| node.name == name),
+			_ => None,
+		}
+	}
+
+	// Adds `node` or replaces an entry of the same name. Later entries in an
+	// archive override earlier ones, except that a directory keeps its contents.
+	fn insert(&mut self, node: Node)
+	{
+		if let Some(existing) = self.child_mut(&node.name)
+		{
+			if existing.is_directory() && node.is_directory()
+			{
+				existing.mode = node.mode;
+			}
+			else
+			{
+				*existing = node;
+			}
+			return;
+		}
+		if let NodeKind::Directory(ref mut children) = self.kind
+		{
+			children.push(node);
+		}
+	}
+}
+
+static ROOT: Once<Node> = once!();
+
+/// Builds the tree from the first boot module that is a USTAR archive. Needs
+/// the heap and the boot information.
+pub fn init()
+{
+	let module = match boot::info().modules().iter().find(|module| tar::is_archive(module.data()))
+	{
+		Some(module) => *module,
+		None => { log!(Info, "No initial ramdisk"); return },
+	};
+
+	let mut root = Node::directory("", DEFAULT_DIRECTORY_MODE);
+	let mut count = 0;
+	let mut long_name = None;
+	for entry in tar::entries(module.data())
+	{
+		let mut entry = match entry
+		{
+			Ok(entry) => entry,
+			Err(error) =>
+			{
+				log!(Warning, "Initial ramdisk {}: {}", module.cmdline.as_str(), error.description());
+				break;
+			},
+		};
+		if let Some(name) = long_name.take()
+		{
+			entry.name = name;
+		}
+
+		let kind = match entry.kind
+		{
+			EntryKind::File => NodeKind::File(entry.data),
+			EntryKind::Directory => NodeKind::Directory(Vec::new()),
+			EntryKind::Symlink => NodeKind::Symlink(entry.link),
+			EntryKind::LongName =>
+			{
+				long_name = Some(String::from_str(tar::field(entry.data)));
+				continue;
+			},
+			EntryKind::Other(kind) =>
+			{
+				log!(Debug, "Initial ramdisk: skipping {} of type {}", entry.name, kind as char);
+				continue;
+			},
+		};
+		if add(&mut root, &entry.name, entry.mode, kind) { count += 1; }
+	}
+
+	// The tree cannot be changed once mounted, so the directories other
+	// filesystems get mounted on are added now
+	for &(name, mode) in fs::MOUNT_POINTS.iter()
+	{
+		if root.child(name).is_none()
+		{
+			root.insert(Node::directory(name, mode));
+		}
+	}
+
+	log!(Info, "Initial ramdisk {} with {} entries", module.cmdline.as_str(), count);
+	let root = ROOT.call_once(move || root);
+	if let Err(error) = fs::mount("/", Box::new(InitrdFileSystem { root: root }) as Box<FileSystem>)
+	{
+		log!(Error, "Could not mount the initial ramdisk: {}", error.description());
+	}
+}
+
+// Puts a node at `path` below `root`, creating the directories on the way
+fn add(root: &mut Node, path: &str, mode: u32, kind: NodeKind) -> bool
+{
+	let mut components: Vec<&str> = path.split('/').filter(|c| *c != "" && *c != ".").collect();
+	// The archive is not supposed to reach out of its root
+	if components.iter().any(|c| *c == "..")
+	{
+		log!(Warning, "Initial ramdisk: skipping {}, it has .. in its path", path);
+		return false;
+	}
+	let name = match components.pop()
+	{
+		Some(name) => name,
+		// The archive's own "./" entry
+		None => return false,
+	};
+
+	let mut directory = root;
+	for component in components.iter()
+	{
+		if directory.child(component).map(|node| !node.is_directory()).unwrap_or(true)
+		{
+			directory.insert(Node::directory(component, DEFAULT_DIRECTORY_MODE));
+		}
+		directory = match { directory }.child_mut(component)
+		{
+			Some(child) => child,
+			None => return false,
+		};
+	}
+	directory.insert(Node { name: String::from_str(name), mode: mode, kind: kind });
+	true
+}
+
+/// The root directory, None when no ramdisk was loaded
+pub fn root() -> Option<&'static Node>
+{
+	ROOT.get()
+}
+
+pub struct InitrdFileSystem
+{
+	root: &'static Node
+}
+
+impl FileSystem for InitrdFileSystem
+{
+	fn name(&self) -> &'static str
+	{
+		"initrd"
+	}
+
+	fn root(&self) -> InodeRef
+	{
+		inode(self.root)
+	}
+}
+
+struct NodeInode
+{
+	node: &'static Node
+}
+
+fn inode(node: &'static Node) -> InodeRef
+{
+	Arc::new(Box::new(NodeInode { node: node }) as Box<Inode>)
+}
+
+fn file_type(node: &Node) -> FileType
+{
+	match node.kind
+	{
+		NodeKind::File(_) => FileType::File,
+		NodeKind::Directory(_) => FileType::Directory,
+		NodeKind::Symlink(_) => FileType::Symlink,
+	}
+}
+
+// Nodes never move, so their address identifies them
+fn inode_number(node: &Node) -> u64
+{
+	node as *const Node as u64
+}
+
+impl Inode for NodeInode
+{
+	fn stat(&self) -> Stat
+	{
+		Stat { inode: inode_number(self.node), kind: file_type(self.node), mode: self.node.mode, size: self.node.size() as u64 }
+	}
+
+	fn lookup(&self, name: &str) -> Result<InodeRef, FsError>
+	{
+		if !self.node.is_directory() { return Err(FsError::NotDirectory) }
+		match self.node.child(name)
+		{
+			Some(child) => Ok(inode(child)),
+			None => Err(FsError::NotFound),
+		}
+	}
+
+	fn readdir(&self) -> Result<Vec<DirEntry>, FsError>
+	{
+		if !self.node.is_directory() { return Err(FsError::NotDirectory) }
+		Ok(self.node.children().iter().map(|child| DirEntry
+		{
+			name: child.name.clone(),
+			inode: inode_number(child),
+			kind: file_type(child)
+		}).collect())
+	}
+
+	fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, FsError>
+	{
+		let data = match self.node.kind
+		{
+			NodeKind::File(data) => data,
+			NodeKind::Directory(_) => return Err(FsError::IsDirectory),
+			NodeKind::Symlink(_) => return Err(FsError::InvalidArgument),
+		};
+		if offset >= data.len() as u64 { return Ok(0) }
+
+		let mut count = 0;
+		for (target, &byte) in buffer.iter_mut().zip(data[offset as usize ..].iter())
+		{
+			*target = byte;
+			count += 1;
+		}
+		Ok(count)
+	}
+}
@@ -0,0 +1,196 @@
+/*
+ * USTAR archive headers
+ *
+ * See: http://pubs.opengroup.org/onlinepubs/9699919799/utilities/pax.html#tag_20_92_13_06
+ *
+ * An archive is a sequence of 512 byte blocks: a header, then the contents
+ * padded to a whole block, and two zero blocks at the end. Numbers are ASCII
+ * octal. GNU tar writes names longer than 100 bytes as a separate 'L' entry
+ * whose contents are the name of the entry after it.
+ */
+
+use core::prelude::*;
+use core::str;
+use collections::string::String;
+
+pub const BLOCK_SIZE: usize = 512;
+
+const MAGIC: &'static [u8] = b"ustar";
+const MAGIC_OFFSET: usize = 257;
+
+pub enum EntryKind
+{
+	File,
+	Directory,
+	Symlink,
+	/// A GNU long name for the next entry
+	LongName,
+	/// Hard links, devices, FIFOs and extended headers
+	Other(u8),
+}
+
+impl Copy for EntryKind {}
+impl Clone for EntryKind { fn clone(&self) -> Self { *self } }
+
+pub struct Entry
+{
+	pub name: String,
+	pub mode: u32,
+	pub kind: EntryKind,
+	pub link: String,
+	pub data: &'static [u8]
+}
+
+pub enum TarError
+{
+	/// A header or contents run past the end of the archive
+	Truncated,
+	BadChecksum,
+	BadNumber,
+}
+
+impl Copy for TarError {}
+impl Clone for TarError { fn clone(&self) -> Self { *self } }
+
+impl TarError
+{
+	pub fn description(&self) -> &'static str
+	{
+		match *self
+		{
+			TarError::Truncated => "archive is truncated",
+			TarError::BadChecksum => "header checksum mismatch",
+			TarError::BadNumber => "header field is not an octal number",
+		}
+	}
+}
+
+/// Whether `data` starts with a USTAR header
+pub fn is_archive(data: &[u8]) -> bool
+{
+	data.len() >= BLOCK_SIZE && &data[MAGIC_OFFSET .. MAGIC_OFFSET + MAGIC.len()] == MAGIC
+}
+
+/// Iterates over the entries of the archive in `data`
+pub fn entries(data: &'static [u8]) -> Entries
+{
+	Entries { data: data, offset: 0, done: false }
+}
+
+pub struct Entries
+{
+	data: &'static [u8],
+	offset: usize,
+	done: bool
+}
+
+impl Iterator for Entries
+{
+	type Item = Result<Entry, TarError>;
+
+	fn next(&mut self) -> Option<Result<Entry, TarError>>
+	{
+		if self.done { return None }
+		let result = self.read_entry();
+		match result
+		{
+			Ok(None) => { self.done = true; None },
+			Ok(Some(entry)) => Some(Ok(entry)),
+			Err(error) => { self.done = true; Some(Err(error)) },
+		}
+	}
+}
+
+impl Entries
+{
+	fn read_entry(&mut self) -> Result<Option<Entry>, TarError>
+	{
+		// Some archivers leave out the end blocks
+		if self.offset == self.data.len() { return Ok(None) }
+		if self.offset + BLOCK_SIZE > self.data.len() { return Err(TarError::Truncated) }
+
+		let header = &self.data[self.offset .. self.offset + BLOCK_SIZE];
+		if header.iter().all(|&b| b == 0) { return Ok(None) }
+
+		if try!(octal(&header[148 .. 156])) != checksum(header) { return Err(TarError::BadChecksum) }
+
+		let size = try!(octal(&header[124 .. 136])) as usize;
+		let start = self.offset + BLOCK_SIZE;
+		if size > self.data.len() - start { return Err(TarError::Truncated) }
+		// Rounding up cannot overflow, size is at most the length of the data
+		self.offset = start + (size + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE;
+		if self.offset > self.data.len() { self.offset = self.data.len(); }
+
+		let kind = match header[156]
+		{
+			b'0' | 0 => EntryKind::File,
+			b'2' => EntryKind::Symlink,
+			b'5' => EntryKind::Directory,
+			b'L' => EntryKind::LongName,
+			other => EntryKind::Other(other),
+		};
+
+		let mut name = String::new();
+		let prefix = field(&header[345 .. 500]);
+		if prefix.len() > 0
+		{
+			name.push_str(prefix);
+			name.push('/');
+		}
+		name.push_str(field(&header[0 .. 100]));
+
+		Ok(Some(Entry
+		{
+			name: name,
+			mode: try!(octal(&header[100 .. 108])),
+			kind: kind,
+			link: String::from_str(field(&header[157 .. 257])),
+			data: &self.data[start .. start + size]
+		}))
+	}
+}
+
+/// The text of a NUL padded field, cut short where it stops being UTF-8
+pub fn field(bytes: &[u8]) -> &str
+{
+	let mut length = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+	loop
+	{
+		match str::from_utf8(&bytes[.. length])
+		{
+			Ok(s) => return s,
+			Err(_) => length -= 1,
+		}
+	}
+}
+
+fn octal(bytes: &[u8]) -> Result<u32, TarError>
+{
+	let mut value = 0u32;
+	for &b in bytes.iter()
+	{
+		match b
+		{
+			b'0' ... b'7' =>
+			{
+				// Sizes of 4GiB and more do not fit, and could not be in memory anyway
+				value = match value.checked_mul(8).and_then(|v| v.checked_add((b - b'0') as u32))
+				{
+					Some(value) => value,
+					None => return Err(TarError::BadNumber),
+				};
+			},
+			// Fields end with a NUL or a space, and some writers pad with spaces in front
+			b' ' if value == 0 => {},
+			b' ' | 0 => break,
+			_ => return Err(TarError::BadNumber),
+		}
+	}
+	Ok(value)
+}
+
+// The sum of all header bytes, with the checksum field counted as spaces
+fn checksum(header: &[u8]) -> u32
+{
+	header.iter().enumerate().fold(0, |sum, (i, &b)| sum + if i >= 148 && i < 156 { b' ' as u32 } else { b as u32 })
+}
@@ -0,0 +1,140 @@
+use core::prelude::*;
+use core::fmt::Write;
+use kernel::stdio::StdioWriter;
+use platform::cpu::InterruptArguments;
+use platform::vga::Color;
+
+struct Exception
+{
+	mnemonic: &'static str,
+	name: &'static str,
+	selector_error: bool,
+	fatal: bool
+}
+
+static EXCEPTIONS: [Exception; 32] = [
+	Exception { mnemonic: "#DE", name: "Divide Error", selector_error: false, fatal: true },
+	Exception { mnemonic: "#DB", name: "Debug", selector_error: false, fatal: false },
+	Exception { mnemonic: "NMI", name: "Non-Maskable Interrupt", selector_error: false, fatal: true },
+	Exception { mnemonic: "#BP", name: "Breakpoint", selector_error: false, fatal: false },
+	Exception { mnemonic: "#OF", name: "Overflow", selector_error: false, fatal: true },
+	Exception { mnemonic: "#BR", name: "BOUND Range Exceeded", selector_error: false, fatal: true },
+	Exception { mnemonic: "#UD", name: "Invalid Opcode", selector_error: false, fatal: true },
+	Exception { mnemonic: "#NM", name: "Device Not Available", selector_error: false, fatal: true },
+	Exception { mnemonic: "#DF", name: "Double Fault", selector_error: false, fatal: true },
+	Exception { mnemonic: "CSO", name: "Coprocessor Segment Overrun", selector_error: false, fatal: true },
+	Exception { mnemonic: "#TS", name: "Invalid TSS", selector_error: true, fatal: true },
+	Exception { mnemonic: "#NP", name: "Segment Not Present", selector_error: true, fatal: true },
+	Exception { mnemonic: "#SS", name: "Stack-Segment Fault", selector_error: true, fatal: true },
+	Exception { mnemonic: "#GP", name: "General Protection", selector_error: true, fatal: true },
+	Exception { mnemonic: "#PF", name: "Page Fault", selector_error: false, fatal: true },
+	Exception { mnemonic: "---", name: "Reserved", selector_error: false, fatal: true },
+	Exception { mnemonic: "#MF", name: "x87 Floating-Point Error", selector_error: false, fatal: true },
+	Exception { mnemonic: "#AC", name: "Alignment Check", selector_error: false, fatal: true },
+	Exception { mnemonic: "#MC", name: "Machine Check", selector_error: false, fatal: true },
+	Exception { mnemonic: "#XM", name: "SIMD Floating-Point", selector_error: false, fatal: true },
+	Exception { mnemonic: "#VE", name: "Virtualization", selector_error: false, fatal: true },
+	Exception { mnemonic: "---", name: "Reserved", selector_error: false, fatal: true },
+	Exception { mnemonic: "---", name: "Reserved", selector_error: false, fatal: true },
+	Exception { mnemonic: "---", name: "Reserved", selector_error: false, fatal: true },
+	Exception { mnemonic: "---", name: "Reserved", selector_error: false, fatal: true },
+	Exception { mnemonic: "---", name: "Reserved", selector_error: false, fatal: true },
+	Exception { mnemonic: "---", name: "Reserved", selector_error: false, fatal: true },
+	Exception { mnemonic: "---", name: "Reserved", selector_error: false, fatal: true },
+	Exception { mnemonic: "---", name: "Reserved", selector_error: false, fatal: true },
+	Exception { mnemonic: "---", name: "Reserved", selector_error: false, fatal: true },
+	Exception { mnemonic: "#SX", name: "Security Exception", selector_error: true, fatal: true },
+	Exception { mnemonic: "---", name: "Reserved", selector_error: false, fatal: true },
+];
+
+pub fn handle_exception(args: &InterruptArguments)
+{
+	let exception = &EXCEPTIONS[args.interrupt_number as usize];
+
+	if !exception.fatal
+	{
+		// Traps like int3 only get a note, the interrupted code carries on
+		let mut printer = StdioWriter::new();
+		printer.fg = Color::Black;
+		printer.bg = Color::White;
+		printer.go_to(10, 5);
+		let _ = write!(&mut printer, "{} {} at EIP=0x{:08x}", exception.mnemonic, exception.name, args.eip);
+		return;
+	}
+
+	if args.from_user_mode()
+	{
+		kill_user_thread(args);
+		return;
+	}
+
+	crash_screen(args);
+	::platform::cpu::halt();
+}
+
+/// Ends the user thread that caused `args`, the kernel itself is unharmed.
+/// The exit code is 128 plus the vector, the way shells report signals.
+pub fn kill_user_thread(args: &InterruptArguments)
+{
+	let exception = &EXCEPTIONS[(args.interrupt_number % 32) as usize];
+	let ::kernel::thread::ThreadId(id) = ::kernel::thread::current_id();
+	log!(Warning, "Thread {} killed: {} {} at EIP=0x{:08x}", id, exception.mnemonic, exception.name, args.eip);
+	::kernel::thread::kill_current(128 + args.interrupt_number as usize);
+}
+
+/// Clears the screen and prints the exception name and the saved registers.
+/// The returned printer sits below the dump so callers can add details.
+pub fn crash_screen(args: &InterruptArguments) -> StdioWriter
+{
+	let vector = args.interrupt_number;
+	let exception = &EXCEPTIONS[(vector % 32) as usize];
+
+	let mut printer = StdioWriter::new();
+	printer.fg = Color::White;
+	printer.bg = Color::Blue;
+	printer.clear_screen();
+
+	printer.fg = Color::Yellow;
+	let _ = write!(&mut printer, "CPU EXCEPTION {} {} (vector {})", exception.mnemonic, exception.name, vector);
+	printer.fg = Color::White;
+	printer.crlf();
+	printer.crlf();
+
+	let _ = write!(&mut printer, "Error code: 0x{:08x}", args.error_code);
+	if exception.selector_error && args.error_code != 0
+	{
+		let _ = write!(&mut printer, "  ");
+		print_selector_error(&mut printer, args.error_code);
+	}
+	printer.crlf();
+	printer.crlf();
+
+	let _ = write!(&mut printer, "EAX=0x{:08x}  EBX=0x{:08x}  ECX=0x{:08x}  EDX=0x{:08x}", args.eax, args.ebx, args.ecx, args.edx);
+	printer.crlf();
+	let _ = write!(&mut printer, "ESI=0x{:08x}  EDI=0x{:08x}  EBP=0x{:08x}  ESP=0x{:08x}", args.esi, args.edi, args.ebp, args.stack_pointer());
+	printer.crlf();
+	let _ = write!(&mut printer, "EIP=0x{:08x}  EFLAGS=0x{:08x}", args.eip, args.eflags);
+	printer.crlf();
+	let _ = write!(&mut printer, "CS=0x{:04x}  DS=0x{:04x}", args.cs & 0xFFFF, args.ds & 0xFFFF);
+	if args.from_user_mode()
+	{
+		let _ = write!(&mut printer, "  SS=0x{:04x}  (user mode)", args.ss & 0xFFFF);
+	}
+	printer.crlf();
+	printer.crlf();
+
+	printer
+}
+
+/// Decodes the error code pushed for segment related exceptions
+fn print_selector_error(printer: &mut StdioWriter, error_code: u32)
+{
+	let table = match (error_code >> 1) & 0x3
+	{
+		0 => "GDT",
+		2 => "LDT",
+		_ => "IDT",
+	};
+	let _ = write!(printer, "selector {} index {}{}", table, (error_code >> 3) & 0x1FFF,
+		if error_code & 0x1 != 0 { " (external)" } else { "" });
+}
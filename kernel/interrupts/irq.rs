@@ -0,0 +1,89 @@
+/*
+ * Table of handlers for the 16 PIC interrupt lines
+ *
+ * Drivers register their handlers at init time. A line can be shared by up to
+ * HANDLERS_PER_IRQ handlers, which are all called when the line fires.
+ */
+
+use core::prelude::*;
+use kernel::sync::IrqLock;
+
+pub type IrqHandler = fn();
+
+pub const IRQ_COUNT: u32 = 16;
+const HANDLERS_PER_IRQ: usize = 4;
+const CASCADE_IRQ: u32 = 2;
+
+static HANDLERS: IrqLock<[[Option<IrqHandler>; HANDLERS_PER_IRQ]; IRQ_COUNT as usize]> = irq_lock!([[None; HANDLERS_PER_IRQ]; IRQ_COUNT as usize]);
+
+/// Adds `handler` to the handlers of `irq` and unmasks the line
+pub fn register_irq_handler(irq: u32, handler: IrqHandler) -> bool
+{
+	if irq >= IRQ_COUNT { return false }
+
+	let mut handlers = HANDLERS.lock();
+	for slot in handlers[irq as usize].iter_mut()
+	{
+		if slot.is_none()
+		{
+			*slot = Some(handler);
+			::platform::cpu::enable_irq(irq);
+			if irq >= 8
+			{
+				::platform::cpu::enable_irq(CASCADE_IRQ);
+			}
+			return true;
+		}
+	}
+	log!(Error, "IRQ {} has no free handler slot", irq);
+	false
+}
+
+/// Removes `handler` from `irq`, masking the line when no handlers are left
+pub fn unregister_irq_handler(irq: u32, handler: IrqHandler) -> bool
+{
+	if irq >= IRQ_COUNT { return false }
+
+	let mut found = false;
+	let mut remaining = 0;
+	let mut handlers = HANDLERS.lock();
+	for slot in handlers[irq as usize].iter_mut()
+	{
+		match *slot
+		{
+			Some(h) if !found && h as usize == handler as usize =>
+			{
+				*slot = None;
+				found = true;
+			},
+			Some(_) => { remaining += 1; },
+			None => {},
+		}
+	}
+
+	if found && remaining == 0 && irq != CASCADE_IRQ
+	{
+		::platform::cpu::disable_irq(irq);
+	}
+	found
+}
+
+pub fn dispatch(irq: u32)
+{
+	let mut handled = false;
+	// Copied out so handlers can (un)register while the line is being serviced
+	let line = HANDLERS.lock()[irq as usize];
+	for slot in line.iter()
+	{
+		if let Some(handler) = *slot
+		{
+			handler();
+			handled = true;
+		}
+	}
+
+	if !handled
+	{
+		log!(Warning, "Unhandled IRQ {}", irq);
+	}
+}
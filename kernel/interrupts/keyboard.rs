@@ -1,28 +1,66 @@
 use kernel::stdio::StdioWriter;
 use kernel::keyboard::*;
+use kernel::thread;
+use kernel::thread::Priority;
+use kernel::sync::Mutex;
 use platform::vga::Color;
 
-static mut shift: u32 = 0;
-static mut irqprinter: StdioWriter = StdioWriter{ xpos: 0, ypos: 4, fg: Color::Yellow, bg: Color::LightRed };
+struct EchoState
+{
+	shift: u32,
+	printer: StdioWriter
+}
+
+static ECHO: Mutex<EchoState> = mutex!(EchoState { shift: 0, printer: StdioWriter{ xpos: 0, ypos: 4, fg: Color::Yellow, bg: Color::LightRed } });
+
+pub fn init()
+{
+	::kernel::keyboard::init();
+	::kernel::interrupts::irq::register_irq_handler(1, keyboard_irq);
+	// Echoing runs at high priority so typing stays responsive under load
+	thread::spawn_with_priority(echo_thread, 0, Priority::High);
+}
 
 pub fn keyboard_irq()
 {
-	let mut printer = unsafe { irqprinter };
-	match ::kernel::keyboard::get_key()
+	push_event(::kernel::keyboard::get_key());
+}
+
+fn echo_thread(_: usize) -> usize
+{
+	let listener = match listen()
+	{
+		Some(listener) => listener,
+		None => { log!(Warning, "No keyboard listener left for echoing"); return 1 },
+	};
+	loop
+	{
+		// Kernel threads cannot be killed, so there always is an event
+		if let Some(action) = listener.read_event()
+		{
+			echo(action);
+		}
+	}
+}
+
+fn echo(action: KeyboardAction)
+{
+	let mut state = ECHO.lock();
+	let shifted = state.shift != 0;
+	match action
 	{
 		KeyboardAction::KeyUp(KeyboardKey::Escape) => { ::platform::cpu::request_int3(); },
-		KeyboardAction::KeyUp(KeyboardKey::Shift) => unsafe { shift -= 1; },
+		KeyboardAction::KeyUp(KeyboardKey::Shift) => { if state.shift > 0 { state.shift -= 1; } },
 		KeyboardAction::KeyDown(key) => match key
 		{
-			KeyboardKey::Printable(c, d) => { printer.print_char(if unsafe {shift == 0} {c} else {d}); },
-			KeyboardKey::Backspace => { printer.backspace(); },
-			KeyboardKey::Return => { printer.crlf(); },
-			KeyboardKey::Shift => unsafe { shift += 1; },
-			KeyboardKey::Tab => { printer.tab(); },
-			KeyboardKey::Unknown(c) => { printer.print_hex(c as u32, 8); printer.print_char(' '); },
+			KeyboardKey::Printable(c, d) => { state.printer.print_char(if shifted {d} else {c}); },
+			KeyboardKey::Backspace => { state.printer.backspace(); },
+			KeyboardKey::Return => { state.printer.crlf(); },
+			KeyboardKey::Shift => { state.shift += 1; },
+			KeyboardKey::Tab => { state.printer.tab(); },
+			KeyboardKey::Unknown(c) => { state.printer.print_hex(c as u32, 8); state.printer.print_char(' '); },
 			_ => {},
 		},
 		_ => {},
 	};
-	unsafe { irqprinter = printer; };
 }
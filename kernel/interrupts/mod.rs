@@ -1,19 +1,43 @@
 use kernel::stdio::StdioWriter;
+use platform::cpu::InterruptArguments;
 use platform::vga::Color;
 
 mod timer;
 mod keyboard;
+pub mod pagefault;
+pub mod exceptions;
+pub mod irq;
 
-pub fn handle_interrupt(interrupt_number: u32, error_code: u32)
+static IRQ_BASE: u32 = 0x20;
+
+pub fn init()
+{
+	irq::register_irq_handler(0, timer::handle_irq);
+	keyboard::init();
+}
+
+pub fn handle_interrupt(args: &mut InterruptArguments)
 {
-	match interrupt_number
+	match args.interrupt_number
 	{
-		0x20 => timer::handle_irq(),
-		0x21 => keyboard::keyboard_irq(),
-		_ => unknown_irq(interrupt_number, error_code),
+		0x0E => pagefault::handle_page_fault(args),
+		0x00 ... 0x1F => exceptions::handle_exception(args),
+		n if n >= IRQ_BASE && n < IRQ_BASE + irq::IRQ_COUNT => irq::dispatch(n - IRQ_BASE),
+		n if n == ::platform::cpu::YIELD_VECTOR => ::kernel::thread::request_switch(),
+		n if n == ::platform::cpu::SYSCALL_VECTOR => ::kernel::syscall::handle_syscall(args),
+		_ => unknown_irq(args.interrupt_number, args.error_code),
 	};
 }
 
+/// Called on the double fault task's stack, so this works even when the
+/// kernel stack has overflowed
+pub fn handle_double_fault(args: &InterruptArguments) -> !
+{
+	let mut printer = exceptions::crash_screen(args);
+	printer.print_screen("Handled on the double fault stack, the kernel stack may have overflowed");
+	::platform::cpu::halt();
+}
+
 fn unknown_irq(interrupt_number: u32, error_code: u32)
 {
 	let mut printer = StdioWriter::new();
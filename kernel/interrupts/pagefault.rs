@@ -0,0 +1,178 @@
+use core::prelude::*;
+use core::fmt::Write;
+use kernel::stdio::StdioWriter;
+use platform::cpu::InterruptArguments;
+use platform::mmu::{frame, paging};
+use kernel::interrupts::exceptions;
+use kernel::sync::IrqLock;
+
+const ERROR_PRESENT: u32 = 1 << 0;
+const ERROR_WRITE: u32 = 1 << 1;
+const ERROR_USER: u32 = 1 << 2;
+const ERROR_RESERVED: u32 = 1 << 3;
+const ERROR_FETCH: u32 = 1 << 4;
+
+const DEMAND_REGION_COUNT: usize = 8;
+
+pub struct PageFault
+{
+	pub address: u32,
+	pub eip: u32,
+	pub present: bool,
+	pub write: bool,
+	pub user: bool,
+	pub reserved: bool,
+	pub instruction_fetch: bool
+}
+
+impl Copy for PageFault {}
+impl Clone for PageFault { fn clone(&self) -> Self { *self } }
+
+impl PageFault
+{
+	pub fn decode(address: u32, eip: u32, error_code: u32) -> PageFault
+	{
+		PageFault
+		{
+			address: address,
+			eip: eip,
+			present: error_code & ERROR_PRESENT != 0,
+			write: error_code & ERROR_WRITE != 0,
+			user: error_code & ERROR_USER != 0,
+			reserved: error_code & ERROR_RESERVED != 0,
+			instruction_fetch: error_code & ERROR_FETCH != 0
+		}
+	}
+}
+
+/// A range of virtual memory that is backed by zeroed frames on first access
+struct DemandRegion
+{
+	start: u32,
+	end: u32,
+	flags: u32
+}
+
+impl Copy for DemandRegion {}
+impl Clone for DemandRegion { fn clone(&self) -> Self { *self } }
+
+static DEMAND_REGIONS: IrqLock<[DemandRegion; DEMAND_REGION_COUNT]> = irq_lock!([DemandRegion { start: 0, end: 0, flags: 0 }; DEMAND_REGION_COUNT]);
+
+/// Lets faults in [start, end) be resolved by mapping a zeroed frame with `flags`
+pub fn add_demand_region(start: u32, end: u32, flags: u32) -> bool
+{
+	for region in DEMAND_REGIONS.lock().iter_mut()
+	{
+		if region.start == region.end
+		{
+			*region = DemandRegion { start: start, end: end, flags: flags };
+			return true;
+		}
+	}
+	false
+}
+
+pub fn remove_demand_region(start: u32)
+{
+	for region in DEMAND_REGIONS.lock().iter_mut()
+	{
+		if region.start == start && region.end != start
+		{
+			*region = DemandRegion { start: 0, end: 0, flags: 0 };
+		}
+	}
+}
+
+pub fn handle_page_fault(args: &InterruptArguments)
+{
+	let fault = PageFault::decode(paging::fault_address(), args.eip, args.error_code);
+
+	if try_resolve(&fault) { return }
+
+	if args.from_user_mode()
+	{
+		log!(Warning, "User page fault at 0x{:08x}", fault.address);
+		exceptions::kill_user_thread(args);
+	}
+	else
+	{
+		let mut printer = exceptions::crash_screen(args);
+		report(&mut printer, &fault);
+		::platform::cpu::halt();
+	}
+}
+
+fn try_resolve(fault: &PageFault) -> bool
+{
+	if fault.reserved { return false }
+
+	// Writes to pages shared by fork get a private copy
+	if fault.present && fault.write && paging::is_user_address(fault.address)
+	{
+		return paging::resolve_copy_on_write(fault.address);
+	}
+
+	// Other protection violations cannot be fixed by mapping a page
+	if fault.present { return false }
+
+	if ::kernel::heap::contains(fault.address)
+	{
+		if fault.user { return false }
+		// The heap reserved a frame for the page when it grew
+		return map_zeroed(fault.address, paging::WRITABLE, frame::alloc_reserved_frame());
+	}
+
+	map_demand_page(fault.address, fault.user)
+}
+
+/// Maps the page holding `address` if it lies in a demand region, and for
+/// `user` access only if the region is mapped with paging::USER. Returns false
+/// for addresses outside the regions and when out of frames.
+pub fn map_demand_page(address: u32, user: bool) -> bool
+{
+	let regions = *DEMAND_REGIONS.lock();
+	for region in regions.iter()
+	{
+		if address >= region.start && address < region.end
+		{
+			if user && region.flags & paging::USER == 0 { return false }
+			return map_zeroed(address, region.flags, frame::alloc_frame());
+		}
+	}
+	false
+}
+
+fn map_zeroed(address: u32, flags: u32, frame: Option<u32>) -> bool
+{
+	let page = address & !(paging::PAGE_SIZE - 1);
+	match frame
+	{
+		Some(phys) =>
+		{
+			// Map writable first so the page can be cleared, then apply the real flags
+			paging::map(page, phys, paging::WRITABLE);
+			unsafe
+			{
+				for i in (0 .. paging::PAGE_SIZE / 4)
+				{
+					*((page + i * 4) as *mut u32) = 0;
+				}
+			}
+			paging::map(page, phys, flags);
+			true
+		},
+		None => false,
+	}
+}
+
+fn report(printer: &mut StdioWriter, fault: &PageFault)
+{
+	let _ = write!(printer, "Address: 0x{:08x}  EIP: 0x{:08x}", fault.address, fault.eip);
+	printer.crlf();
+	let _ = write!(printer, "{} {} in {} mode{}",
+		if fault.instruction_fetch { "Instruction fetch" } else if fault.write { "Write" } else { "Read" },
+		if fault.present { "violated page protection" } else { "hit a non-present page" },
+		if fault.user { "user" } else { "kernel" },
+		if fault.reserved { ", reserved bit set in page tables" } else { "" });
+	printer.crlf();
+}
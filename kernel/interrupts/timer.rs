@@ -1,18 +1,9 @@
-use kernel::stdio::StdioWriter;
-use platform::vga::Color;
-
-static mut tick: u32 = 48;
+use kernel::time;
+use kernel::thread;
 
 pub fn handle_irq()
 {
-	let mut printer = StdioWriter { xpos: 0, ypos: 10, fg: Color::White, bg: Color::Black };
-	let mytick = unsafe
-	{
-		tick = (tick + 1) % 50;
-		tick
-	};
-	if mytick % 25 == 0
-	{
-		printer.print_screen(if mytick < 25 { "tick" } else { "tock" });
-	}
+	time::tick();
+	time::wheel::run_timers();
+	thread::tick();
 }
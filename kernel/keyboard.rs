@@ -1,5 +1,10 @@
+use core::prelude::*;
+use platform::cpu;
 use platform::keyboard;
 use platform::keyboard::ArchKeyboardAction;
+use kernel::thread;
+use kernel::thread::{WaitQueue, WAIT_QUEUE_INIT};
+use kernel::sync::{IrqLock, Once};
 
 pub enum KeyboardKey
 {
@@ -12,21 +17,132 @@ pub enum KeyboardKey
 	Unknown(u8)
 }
 
+impl Copy for KeyboardKey {}
+impl Clone for KeyboardKey { fn clone(&self) -> Self { *self } }
+
 pub enum KeyboardAction
 {
 	KeyUp(KeyboardKey),
 	KeyDown(KeyboardKey),
 }
 
-pub fn get_key() -> KeyboardAction
+impl Copy for KeyboardAction {}
+impl Clone for KeyboardAction { fn clone(&self) -> Self { *self } }
+
+// Events are queued by the keyboard interrupt and consumed by threads. Every
+// listener has a queue of its own and sees every event, so the echo thread,
+// console readers and /dev/kbd do not take keys away from each other.
+const EVENT_BUFFER_SIZE: usize = 64;
+const LISTENER_COUNT: usize = 8;
+
+struct EventBuffer
+{
+	events: [KeyboardAction; EVENT_BUFFER_SIZE],
+	read: usize,
+	count: usize,
+	in_use: bool
+}
+
+impl Copy for EventBuffer {}
+impl Clone for EventBuffer { fn clone(&self) -> Self { *self } }
+
+const EVENT_BUFFER_INIT: EventBuffer = EventBuffer { events: [KeyboardAction::KeyUp(KeyboardKey::Unknown(0)); EVENT_BUFFER_SIZE], read: 0, count: 0, in_use: false };
+
+static EVENTS: IrqLock<[EventBuffer; LISTENER_COUNT]> = irq_lock!([EVENT_BUFFER_INIT; LISTENER_COUNT]);
+static EVENT_WAITERS: WaitQueue = WAIT_QUEUE_INIT;
+
+/// A queue of keyboard events, which receives every event from the moment it
+/// is created until it is dropped
+pub struct Listener
+{
+	index: usize
+}
+
+/// Creates a listener, None when all LISTENER_COUNT are taken
+pub fn listen() -> Option<Listener>
+{
+	let mut buffers = EVENTS.lock();
+	match buffers.iter().position(|buffer| !buffer.in_use)
+	{
+		Some(index) =>
+		{
+			buffers[index] = EventBuffer { in_use: true, .. EVENT_BUFFER_INIT };
+			Some(Listener { index: index })
+		},
+		None => None,
+	}
+}
+
+impl Drop for Listener
+{
+	fn drop(&mut self)
+	{
+		EVENTS.lock()[self.index].in_use = false;
+	}
+}
+
+impl Listener
 {
-	match keyboard::get_key()
+	/// Takes the oldest queued event without blocking
+	pub fn try_read_event(&self) -> Option<KeyboardAction>
 	{
-		ArchKeyboardAction::Down(code) => KeyboardAction::KeyDown(parse_keycode(code)),
-		ArchKeyboardAction::Up(code) => KeyboardAction::KeyUp(parse_keycode(code)),
+		let mut buffers = EVENTS.lock();
+		let buffer = &mut buffers[self.index];
+		if buffer.count == 0
+		{
+			None
+		}
+		else
+		{
+			let action = buffer.events[buffer.read];
+			buffer.read = (buffer.read + 1) % EVENT_BUFFER_SIZE;
+			buffer.count -= 1;
+			Some(action)
+		}
+	}
+
+	/// Blocks until a keyboard event is available. Returns None when the
+	/// calling thread is killed in the meantime.
+	pub fn read_event(&self) -> Option<KeyboardAction>
+	{
+		loop
+		{
+			// Interrupts stay off between the check and blocking, so an event cannot be missed
+			let flags = cpu::disable_interrupts();
+			if let Some(action) = self.try_read_event()
+			{
+				cpu::restore_interrupts(flags);
+				return Some(action);
+			}
+			if thread::interrupted()
+			{
+				cpu::restore_interrupts(flags);
+				return None;
+			}
+			EVENT_WAITERS.wait();
+			cpu::restore_interrupts(flags);
+		}
 	}
 }
 
+/// Hands an event to every listener. A listener whose queue is full misses it.
+pub fn push_event(action: KeyboardAction)
+{
+	{
+		let mut buffers = EVENTS.lock();
+		for buffer in buffers.iter_mut()
+		{
+			if buffer.in_use && buffer.count < EVENT_BUFFER_SIZE
+			{
+				let index = (buffer.read + buffer.count) % EVENT_BUFFER_SIZE;
+				buffer.events[index] = action;
+				buffer.count += 1;
+			}
+		}
+	}
+	EVENT_WAITERS.wake_all();
+}
+
 fn parse_keycode(code: u8) -> KeyboardKey
 {
 	match code
@@ -102,3 +218,46 @@ fn parse_keycode(code: u8) -> KeyboardKey
 		c => KeyboardKey::Unknown(c),
 	}
 }
+
+static READ_SHIFT: IrqLock<u32> = irq_lock!(0);
+
+// Shared by everyone reading characters, like a terminal
+static CONSOLE_LISTENER: Once<Listener> = once!();
+
+/// Starts queueing keys for `read_char`, so that keys typed before the first
+/// read are not lost
+pub fn init()
+{
+	console_listener();
+}
+
+fn console_listener() -> &'static Listener
+{
+	CONSOLE_LISTENER.call_once(|| match listen()
+	{
+		Some(listener) => listener,
+		None => panic!("No keyboard listener left for the console"),
+	})
+}
+
+/// Blocks until a key press produces a character. Return gives '\n' and
+/// backspace gives '\x08'. Characters go to one of the callers only. Returns
+/// None when the calling thread is killed in the meantime.
+pub fn read_char() -> Option<char>
+{
+	loop
+	{
+		let action = match console_listener().read_event() { Some(action) => action, None => return None };
+		let mut shift = READ_SHIFT.lock();
+		match action
+		{
+			KeyboardAction::KeyDown(KeyboardKey::Shift) => { *shift += 1; },
+			KeyboardAction::KeyUp(KeyboardKey::Shift) => { if *shift > 0 { *shift -= 1; } },
+			KeyboardAction::KeyDown(KeyboardKey::Printable(c, d)) => return Some(if *shift != 0 { d } else { c }),
+			KeyboardAction::KeyDown(KeyboardKey::Return) => return Some('\n'),
+			KeyboardAction::KeyDown(KeyboardKey::Backspace) => return Some('\x08'),
+			KeyboardAction::KeyDown(KeyboardKey::Tab) => return Some('\t'),
+			_ => {},
+		}
+	}
+}
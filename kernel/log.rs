@@ -0,0 +1,121 @@
+use core::prelude::*;
+use core::fmt;
+use core::fmt::Write;
+use kernel::stdio::StdioWriter;
+use platform::vga::{Color, COLS};
+use kernel::sync::IrqLock;
+use kernel::cmdline;
+use kernel::cmdline::{Parameter, Kind};
+use platform::serial;
+use platform::serial::SerialWriter;
+
+pub enum Level
+{
+	Error = 0,
+	Warning = 1,
+	Info = 2,
+	Debug = 3,
+}
+
+impl Copy for Level {}
+impl Clone for Level { fn clone(&self) -> Self { *self } }
+
+macro_rules! log {
+	($level:ident, $($arg:tt)*) => (
+		::kernel::log::log(::kernel::log::Level::$level, format_args!($($arg)*))
+	)
+}
+
+// Log lines scroll through the bottom of the screen
+const FIRST_ROW: u32 = 16;
+const ROW_COUNT: u32 = 9;
+
+pub static LOGLEVEL: Parameter = Parameter { name: "loglevel", kind: Kind::Text, description: "error, warning, info, debug or 0 to 3" };
+pub static CONSOLE: Parameter = Parameter { name: "console", kind: Kind::Text, description: "vga or serial, where log lines go" };
+
+struct Logger
+{
+	max_level: Level,
+	next_row: u32,
+	serial: bool
+}
+
+static LOGGER: IrqLock<Logger> = irq_lock!(Logger { max_level: Level::Info, next_row: 0, serial: false });
+
+/// Applies the logging options from the command line
+pub fn init()
+{
+	cmdline::register(&LOGLEVEL);
+	cmdline::register(&CONSOLE);
+
+	if let Some(value) = LOGLEVEL.text()
+	{
+		match parse_level(value)
+		{
+			Some(level) => set_level(level),
+			None => log!(Warning, "Unknown log level '{}'", value),
+		}
+	}
+	match CONSOLE.text()
+	{
+		None | Some("vga") => {},
+		Some("serial") =>
+		{
+			serial::init();
+			LOGGER.lock().serial = true;
+		},
+		Some(value) => log!(Warning, "Unknown console '{}'", value),
+	}
+}
+
+fn parse_level(value: &str) -> Option<Level>
+{
+	match value
+	{
+		"0" | "error" => Some(Level::Error),
+		"1" | "warning" => Some(Level::Warning),
+		"2" | "info" => Some(Level::Info),
+		"3" | "debug" => Some(Level::Debug),
+		_ => None,
+	}
+}
+
+pub fn set_level(level: Level)
+{
+	LOGGER.lock().max_level = level;
+}
+
+pub fn log(level: Level, args: fmt::Arguments)
+{
+	// Holding the lock while printing keeps lines from interleaving
+	let mut logger = LOGGER.lock();
+	if level as u32 > logger.max_level as u32 { return }
+
+	if logger.serial
+	{
+		let mut writer = SerialWriter;
+		let _ = writer.write_fmt(args);
+		let _ = writer.write_str("\n");
+		return;
+	}
+
+	let row = FIRST_ROW + logger.next_row;
+	logger.next_row = (logger.next_row + 1) % ROW_COUNT;
+
+	let mut printer = StdioWriter::new();
+	printer.fg = match level
+	{
+		Level::Error => Color::LightRed,
+		Level::Warning => Color::Yellow,
+		Level::Info => Color::White,
+		Level::Debug => Color::LightGray,
+	};
+	printer.go_to(0, row);
+	for _ in (0 .. COLS - 1)
+	{
+		printer.print_char(' ');
+	}
+	printer.go_to(0, row);
+
+	let _ = printer.write_fmt(args);
+}
@@ -1,14 +1,39 @@
 use platform::vga::Color;
 use kernel::stdio::StdioWriter;
 use core::fmt::Write;
+use collections::vec::Vec;
+use kernel::cmdline;
+use kernel::cmdline::{Parameter, Kind};
+use kernel::process;
+use platform::mmu::frame;
+
+pub static INIT: Parameter = Parameter { name: "init", kind: Kind::Text, description: "path of the first process, a boot module or a file" };
+pub static SELFTEST: Parameter = Parameter { name: "selftest", kind: Kind::Flag, description: "check heap, frames and timer at boot" };
 
 #[no_mangle]
-pub fn entry() -> !
+pub extern "C" fn entry(magic: u32, info: u32) -> !
 {
 	::platform::cpu::setup();
-	::platform::mmu::setup();
+	let boot_info = match ::platform::multiboot::parse(magic, info).or_else(|| ::platform::multiboot2::parse(magic, info))
+	{
+		Some(boot_info) => boot_info,
+		None => panic!("Not started by a multiboot or multiboot2 boot loader (magic 0x{:08x})", magic),
+	};
+	::platform::mmu::setup(&boot_info);
+	::kernel::heap::init();
+	::kernel::boot::init(&boot_info);
+	::kernel::log::init();
+	::kernel::initrd::init();
+	::kernel::fs::init();
+	::kernel::time::init();
+	::kernel::thread::init();
+	::kernel::process::init();
+	::kernel::interrupts::init();
 	::platform::cpu::enable_interrupts();
 	main();
+	// The boot thread only idles from here on, it must not keep threads of
+	// lower priority from running
+	::kernel::thread::set_priority(::kernel::thread::Priority::Idle);
 	loop { ::platform::cpu::idle(); }
 }
 
@@ -21,6 +46,79 @@ fn main()
 	printer.fg = Color::White;
 	printer.go_to(3, 3);
 	printer.print_screen("Hello, World!");
+
+	let boot = ::kernel::boot::info();
+	log!(Info, "Booted by {} with {} KiB of memory and {} modules", boot.bootloader_name.as_str(), boot.available_memory() / 1024, boot.modules().len());
+
+	::kernel::time::wheel::add_periodic_timer(::kernel::time::frequency() / 2, tick_tock, 0);
+
+	cmdline::register(&INIT);
+	cmdline::register(&SELFTEST);
+	if SELFTEST.flag()
+	{
+		selftest();
+	}
+
+	// A module is known by the first word of its command line
+	for module in boot.modules().iter().filter(|module| !::kernel::initrd::tar::is_archive(module.data()))
+	{
+		if let Some(path) = module.cmdline.as_str().split(' ').next()
+		{
+			process::register_image(path, module.data());
+		}
+	}
+	if let Some(path) = INIT.text()
+	{
+		match process::spawn(path, &[])
+		{
+			Ok(_) => {},
+			Err(_) => log!(Error, "Could not start init process {}", path),
+		}
+	}
+
+	cmdline::check();
+}
+
+fn selftest()
+{
+	let mut passed = true;
+
+	let numbers: Vec<u32> = (0 .. 1000).collect();
+	if numbers.iter().fold(0, |total, n| total + *n) != 499500
+	{
+		log!(Error, "Selftest: heap contents are wrong");
+		passed = false;
+	}
+
+	let free = frame::free_count();
+	match frame::alloc_frame()
+	{
+		Some(address) => frame::free_frame(address),
+		None => { log!(Error, "Selftest: no free frame"); passed = false; },
+	}
+	if frame::free_count() != free
+	{
+		log!(Error, "Selftest: frame count changed from {} to {}", free, frame::free_count());
+		passed = false;
+	}
+
+	let start = ::kernel::time::uptime();
+	::kernel::time::sleep_ms(20);
+	if ::kernel::time::uptime() < start + 20
+	{
+		log!(Error, "Selftest: sleep returned early");
+		passed = false;
+	}
+
+	if passed { log!(Info, "Selftest passed"); }
+}
+
+fn tick_tock(_: usize)
+{
+	let mut printer = StdioWriter { xpos: 0, ypos: 10, fg: Color::White, bg: Color::Black };
+	let half_seconds = ::kernel::time::uptime() as u32 / 500;
+	printer.print_screen(if half_seconds % 2 == 0 { "tick" } else { "tock" });
+	let _ = write!(&mut printer, "  {}", ::kernel::time::clock::now_datetime());
 }
 
 #[lang = "panic_fmt"]
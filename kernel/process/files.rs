@@ -0,0 +1,92 @@
+/*
+ * Per-process file descriptor tables
+ *
+ * A descriptor refers to an open file. Copies of a table, as made by fork,
+ * share the open files and with them the position in each file.
+ */
+
+use core::prelude::*;
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+use collections::vec::Vec;
+use kernel::fs::File;
+use kernel::fs::console::{ConsoleInput, ConsoleOutput};
+use kernel::sync::Mutex;
+
+pub const STDIN: u32 = 0;
+pub const STDOUT: u32 = 1;
+pub const STDERR: u32 = 2;
+
+const MAX_FILES: usize = 32;
+
+pub type FileRef = Arc<Mutex<Box<File>>>;
+
+pub struct FileTable
+{
+	files: Vec<Option<FileRef>>
+}
+
+impl Clone for FileTable
+{
+	fn clone(&self) -> FileTable
+	{
+		FileTable { files: self.files.clone() }
+	}
+}
+
+impl FileTable
+{
+	pub fn new() -> FileTable
+	{
+		FileTable { files: Vec::new() }
+	}
+
+	/// A table with stdin, stdout and stderr connected to the console
+	pub fn with_console() -> FileTable
+	{
+		let mut table = FileTable::new();
+		table.insert(Box::new(ConsoleInput) as Box<File>);
+		table.insert(Box::new(ConsoleOutput { error: false }) as Box<File>);
+		table.insert(Box::new(ConsoleOutput { error: true }) as Box<File>);
+		table
+	}
+
+	pub fn get(&self, fd: u32) -> Option<FileRef>
+	{
+		match self.files.get(fd as usize)
+		{
+			Some(&Some(ref file)) => Some(file.clone()),
+			_ => None,
+		}
+	}
+
+	/// Stores `file` under the lowest free number and returns it
+	pub fn insert(&mut self, file: Box<File>) -> Option<u32>
+	{
+		let file = Arc::new(Mutex::new(file));
+		match self.files.iter().position(|f| f.is_none())
+		{
+			Some(index) =>
+			{
+				self.files[index] = Some(file);
+				Some(index as u32)
+			},
+			None if self.files.len() < MAX_FILES =>
+			{
+				self.files.push(Some(file));
+				Some(self.files.len() as u32 - 1)
+			},
+			None => None,
+		}
+	}
+
+	/// The file is closed once no other table refers to it
+	pub fn remove(&mut self, fd: u32) -> Option<FileRef>
+	{
+		match self.files.get_mut(fd as usize)
+		{
+			Some(slot) => slot.take(),
+			None => None,
+		}
+	}
+}
@@ -0,0 +1,331 @@
+/*
+ * User processes
+ *
+ * A process is a program loaded into its own address space and run by one
+ * user thread. The kernel half of every address space is shared, see
+ * paging::create_address_space. Besides its thread a process has a PID, the
+ * PID of the process that started it and a table of open files.
+ *
+ * The exit code of a process is the exit code of its thread. It is kept by the
+ * thread until the parent collects it with `wait`, which also frees the
+ * address space. The children of a process that exits are handed to the
+ * kernel, whose reaper thread collects them and the processes the kernel
+ * started itself.
+ */
+
+use core::prelude::*;
+use alloc::boxed::Box;
+use collections::string::String;
+use collections::vec::Vec;
+use platform::cpu::InterruptArguments;
+use platform::mmu::paging;
+use kernel::elf;
+use kernel::elf::ElfError;
+use kernel::fs;
+use kernel::fs::File;
+use kernel::sync::{Mutex, Once};
+use kernel::thread;
+use kernel::thread::ThreadId;
+
+pub use self::files::{FileTable, FileRef};
+
+pub mod files;
+
+/// Exit code of a killed process, the way shells report SIGKILL
+pub const KILLED_EXIT_CODE: usize = 128 + 9;
+
+pub struct Pid(pub u32);
+
+impl Copy for Pid {}
+impl Clone for Pid { fn clone(&self) -> Self { *self } }
+
+/// Parent of the processes the kernel starts itself
+pub const KERNEL_PID: Pid = Pid(0);
+
+pub enum SpawnError
+{
+	NotFound,
+	Elf(ElfError),
+}
+
+impl Copy for SpawnError {}
+impl Clone for SpawnError { fn clone(&self) -> Self { *self } }
+
+pub struct ProcessInfo
+{
+	pub pid: Pid,
+	pub parent: Pid,
+	pub thread: ThreadId
+}
+
+impl Copy for ProcessInfo {}
+impl Clone for ProcessInfo { fn clone(&self) -> Self { *self } }
+
+struct Process
+{
+	pid: u32,
+	parent: u32,
+	thread: ThreadId,
+	name: String,
+	files: FileTable
+}
+
+struct Image
+{
+	path: String,
+	data: &'static [u8]
+}
+
+struct ProcessTable
+{
+	processes: Vec<Box<Process>>,
+	images: Vec<Image>,
+	next_pid: u32
+}
+
+static TABLE: Once<Mutex<ProcessTable>> = once!();
+
+fn table() -> &'static Mutex<ProcessTable>
+{
+	TABLE.call_once(|| Mutex::new(ProcessTable { processes: Vec::new(), images: Vec::new(), next_pid: 1 }))
+}
+
+/// Starts the reaper thread
+pub fn init()
+{
+	thread::spawn(reaper, 0);
+}
+
+fn reaper(_: usize) -> usize
+{
+	loop
+	{
+		let seen = thread::finished_count();
+		reap();
+		thread::wait_for_finished(seen);
+	}
+}
+
+// Hands the children of exited processes to the kernel, and collects the
+// kernel's children that have exited
+fn reap()
+{
+	let Pid(kernel) = KERNEL_PID;
+	let mut exited = Vec::new();
+	{
+		let mut table = table().lock();
+		let finished: Vec<u32> = table.processes.iter().filter(|p| thread::is_finished(p.thread)).map(|p| p.pid).collect();
+		for process in table.processes.iter_mut()
+		{
+			if finished.contains(&process.parent) { process.parent = kernel; }
+		}
+		let mut index = 0;
+		while index < table.processes.len()
+		{
+			if table.processes[index].parent == kernel && finished.contains(&table.processes[index].pid)
+			{
+				exited.push(table.processes.remove(index));
+			}
+			else
+			{
+				index += 1;
+			}
+		}
+	}
+
+	for process in exited.iter()
+	{
+		if let Some(code) = thread::join(process.thread)
+		{
+			log!(Debug, "Process {} ({}) exited with {}", process.pid, process.name, code);
+		}
+	}
+}
+
+/// Makes an executable in memory available to `spawn` under `path`
+pub fn register_image(path: &str, data: &'static [u8])
+{
+	let mut table = table().lock();
+	table.images.retain(|image| image.path != path);
+	table.images.push(Image { path: String::from_str(path), data: data });
+}
+
+/// Starts the executable at `path` as a child of the calling process, with
+/// `path` as argv[0] followed by `args`. Registered images are looked up
+/// before files in the filesystem.
+pub fn spawn(path: &str, args: &[&str]) -> Result<Pid, SpawnError>
+{
+	let registered = table().lock().images.iter().find(|image| image.path == path).map(|image| image.data);
+	let file;
+	let image = match registered
+	{
+		Some(image) => image,
+		None => match fs::read_file(path)
+		{
+			Ok(data) => { file = data; &file[..] },
+			Err(_) => return Err(SpawnError::NotFound),
+		},
+	};
+
+	let mut argv = Vec::with_capacity(args.len() + 1);
+	argv.push(path);
+	argv.push_all(args);
+	let program = match elf::load(image, &argv, &[])
+	{
+		Ok(program) => program,
+		Err(error) => return Err(SpawnError::Elf(error)),
+	};
+
+	let Pid(parent) = current_pid();
+	// The table stays locked until the process is in it, in case its thread
+	// makes a system call right away
+	let mut table = table().lock();
+	let pid = table.next_pid;
+	table.next_pid += 1;
+	let thread = thread::spawn_user(program.entry, program.stack_pointer, program.directory);
+	table.processes.push(Box::new(Process
+	{
+		pid: pid,
+		parent: parent,
+		thread: thread,
+		name: String::from_str(path),
+		files: FileTable::with_console()
+	}));
+	log!(Debug, "Started process {} ({})", pid, path);
+	Ok(Pid(pid))
+}
+
+/// Duplicates the calling process. The child gets a copy-on-write copy of the
+/// address space and of the file table, and resumes from `frame` with EAX set
+/// to 0. Returns the PID of the child, or None for kernel threads and when
+/// memory runs out.
+pub fn fork(frame: &InterruptArguments) -> Option<Pid>
+{
+	let ThreadId(current) = thread::current_id();
+	let mut table = table().lock();
+	let (parent, name, files) = match table.processes.iter().find(|p| { let ThreadId(t) = p.thread; t == current })
+	{
+		Some(process) => (process.pid, process.name.clone(), process.files.clone()),
+		None => return None,
+	};
+
+	let directory = match paging::clone_address_space() { Some(d) => d, None => return None };
+	let pid = table.next_pid;
+	table.next_pid += 1;
+	let thread = thread::spawn_user_from_frame(frame, 0, directory);
+	table.processes.push(Box::new(Process
+	{
+		pid: pid,
+		parent: parent,
+		thread: thread,
+		name: name,
+		files: files
+	}));
+	log!(Debug, "Process {} forked into {}", parent, pid);
+	Some(Pid(pid))
+}
+
+/// Waits for a child of the calling process to exit and returns its exit
+/// code. Returns None if `pid` is not a child of the caller or the caller is
+/// killed while waiting.
+pub fn wait(pid: Pid) -> Option<usize>
+{
+	let Pid(pid) = pid;
+	let Pid(caller) = current_pid();
+	let Pid(kernel) = KERNEL_PID;
+	let thread = match table().lock().processes.iter().find(|p| p.pid == pid && p.parent == caller)
+	{
+		Some(process) => process.thread,
+		None => return None,
+	};
+
+	// Leaves the child alone when the caller is killed while waiting
+	let code = match thread::join(thread)
+	{
+		Some(code) => code,
+		None => return None,
+	};
+
+	let mut table = table().lock();
+	if let Some(index) = table.processes.iter().position(|p| p.pid == pid)
+	{
+		let process = table.processes.remove(index);
+		log!(Debug, "Process {} ({}) exited with {}", pid, process.name, code);
+	}
+	// Orphans are adopted by the kernel
+	for process in table.processes.iter_mut()
+	{
+		if process.parent == pid { process.parent = kernel; }
+	}
+	Some(code)
+}
+
+/// Ends a process. Its parent still has to `wait` for it.
+pub fn kill(pid: Pid) -> bool
+{
+	let Pid(pid) = pid;
+	let thread = match table().lock().processes.iter().find(|p| p.pid == pid)
+	{
+		Some(process) => process.thread,
+		None => return false,
+	};
+	thread::kill(thread, KILLED_EXIT_CODE)
+}
+
+/// PID of the process the calling thread belongs to, KERNEL_PID for kernel threads
+pub fn current_pid() -> Pid
+{
+	let ThreadId(current) = thread::current_id();
+	match table().lock().processes.iter().find(|p| { let ThreadId(t) = p.thread; t == current })
+	{
+		Some(process) => Pid(process.pid),
+		None => KERNEL_PID,
+	}
+}
+
+/// Looks up a descriptor of the calling process
+pub fn file(fd: u32) -> Option<FileRef>
+{
+	let ThreadId(current) = thread::current_id();
+	match table().lock().processes.iter().find(|p| { let ThreadId(t) = p.thread; t == current })
+	{
+		Some(process) => process.files.get(fd),
+		None => None,
+	}
+}
+
+/// Gives the calling process a descriptor for `file`. Returns None for kernel
+/// threads and when the table is full.
+pub fn add_file(file: Box<File>) -> Option<u32>
+{
+	let ThreadId(current) = thread::current_id();
+	match table().lock().processes.iter_mut().find(|p| { let ThreadId(t) = p.thread; t == current })
+	{
+		Some(process) => process.files.insert(file),
+		None => None,
+	}
+}
+
+/// Closes a descriptor of the calling process
+pub fn close_file(fd: u32) -> bool
+{
+	let ThreadId(current) = thread::current_id();
+	// Closing the file may take locks of its filesystem, so it is dropped after the table is unlocked
+	let file = match table().lock().processes.iter_mut().find(|p| { let ThreadId(t) = p.thread; t == current })
+	{
+		Some(process) => process.files.remove(fd),
+		None => None,
+	};
+	file.is_some()
+}
+
+/// Snapshot of every process, for diagnostics
+pub fn list() -> Vec<ProcessInfo>
+{
+	table().lock().processes.iter().map(|p| ProcessInfo
+	{
+		pid: Pid(p.pid),
+		parent: Pid(p.parent),
+		thread: p.thread
+	}).collect()
+}
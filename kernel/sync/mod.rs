@@ -0,0 +1,54 @@
+/*
+ * Synchronization primitives
+ *
+ * Only IrqLock is safe to share with interrupt handlers: it keeps interrupts
+ * off while held. A plain Spinlock taken by a handler while the interrupted
+ * thread holds it spins forever. Mutex and Semaphore put the calling thread to
+ * sleep and may only be used by threads. Once runs its initializer with
+ * interrupts off, so it may be used from handlers too.
+ *
+ * The fields are public so that the macros below can build locks in statics.
+ */
+
+pub use self::spinlock::{Spinlock, SpinlockGuard, IrqLock, IrqLockGuard};
+pub use self::mutex::{Mutex, MutexGuard};
+pub use self::semaphore::Semaphore;
+pub use self::once::Once;
+
+macro_rules! spinlock {
+	($value:expr) => (::kernel::sync::Spinlock {
+		locked: ::core::sync::atomic::ATOMIC_BOOL_INIT,
+		data: ::core::cell::UnsafeCell { value: $value },
+	})
+}
+
+macro_rules! irq_lock {
+	($value:expr) => (::kernel::sync::IrqLock { inner: spinlock!($value) })
+}
+
+macro_rules! mutex {
+	($value:expr) => (::kernel::sync::Mutex {
+		locked: ::core::sync::atomic::ATOMIC_BOOL_INIT,
+		waiters: ::kernel::thread::WAIT_QUEUE_INIT,
+		data: ::core::cell::UnsafeCell { value: $value },
+	})
+}
+
+macro_rules! semaphore {
+	($count:expr) => (::kernel::sync::Semaphore {
+		count: ::core::cell::UnsafeCell { value: $count },
+		waiters: ::kernel::thread::WAIT_QUEUE_INIT,
+	})
+}
+
+macro_rules! once {
+	() => (::kernel::sync::Once {
+		state: ::core::sync::atomic::ATOMIC_USIZE_INIT,
+		data: ::core::cell::UnsafeCell { value: 0 as *mut _ },
+	})
+}
+
+pub mod spinlock;
+pub mod mutex;
+pub mod semaphore;
+pub mod once;
@@ -0,0 +1,79 @@
+use core::prelude::*;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+use platform::cpu;
+use kernel::thread::WaitQueue;
+
+/// Lock that puts contending threads to sleep instead of spinning
+pub struct Mutex<T>
+{
+	pub locked: AtomicBool,
+	pub waiters: WaitQueue,
+	pub data: UnsafeCell<T>
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+unsafe impl<T: Send> Send for Mutex<T> {}
+
+pub struct MutexGuard<'a, T: 'a>
+{
+	mutex: &'a Mutex<T>
+}
+
+impl<T> Mutex<T>
+{
+	pub fn new(value: T) -> Mutex<T>
+	{
+		Mutex { locked: AtomicBool::new(false), waiters: WaitQueue::new(), data: UnsafeCell::new(value) }
+	}
+
+	pub fn lock(&self) -> MutexGuard<T>
+	{
+		loop
+		{
+			// Interrupts stay off between the check and blocking, so an unlock cannot be missed
+			let flags = cpu::disable_interrupts();
+			if !self.locked.compare_and_swap(false, true, Ordering::Acquire)
+			{
+				cpu::restore_interrupts(flags);
+				return MutexGuard { mutex: self };
+			}
+			self.waiters.wait();
+			cpu::restore_interrupts(flags);
+		}
+	}
+
+	pub fn try_lock(&self) -> Option<MutexGuard<T>>
+	{
+		if self.locked.compare_and_swap(false, true, Ordering::Acquire)
+		{
+			None
+		}
+		else
+		{
+			Some(MutexGuard { mutex: self })
+		}
+	}
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T>
+{
+	type Target = T;
+	fn deref(&self) -> &T { unsafe { &*self.mutex.data.get() } }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T>
+{
+	fn deref_mut(&mut self) -> &mut T { unsafe { &mut *self.mutex.data.get() } }
+}
+
+#[unsafe_destructor]
+impl<'a, T> Drop for MutexGuard<'a, T>
+{
+	fn drop(&mut self)
+	{
+		self.mutex.locked.store(false, Ordering::Release);
+		self.mutex.waiters.wake_one();
+	}
+}
@@ -0,0 +1,57 @@
+use core::prelude::*;
+use core::cell::UnsafeCell;
+use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use alloc::boxed::Box;
+use platform::cpu;
+
+const UNINITIALIZED: usize = 0;
+const RUNNING: usize = 1;
+const INITIALIZED: usize = 2;
+
+/// A value that is set up once and never freed. It is kept on the heap, so a
+/// Once can sit in a static even when the value has a destructor.
+pub struct Once<T>
+{
+	pub state: AtomicUsize,
+	pub data: UnsafeCell<*mut T>
+}
+
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+unsafe impl<T: Send + Sync> Send for Once<T> {}
+
+impl<T> Once<T>
+{
+	/// Runs `init` if no value has been set yet and returns the value. `init`
+	/// runs with interrupts disabled, so that a handler calling this in the
+	/// meantime cannot wait forever for it to finish.
+	pub fn call_once<F: FnOnce() -> T>(&'static self, init: F) -> &'static T
+	{
+		let flags = cpu::disable_interrupts();
+		if self.state.compare_and_swap(UNINITIALIZED, RUNNING, Ordering::SeqCst) == UNINITIALIZED
+		{
+			let value = Box::new(init());
+			unsafe { *self.data.get() = mem::transmute(value); }
+			self.state.store(INITIALIZED, Ordering::SeqCst);
+		}
+		cpu::restore_interrupts(flags);
+
+		while self.state.load(Ordering::SeqCst) != INITIALIZED
+		{
+			cpu::relax();
+		}
+		unsafe { &**self.data.get() }
+	}
+
+	pub fn get(&'static self) -> Option<&'static T>
+	{
+		if self.state.load(Ordering::SeqCst) == INITIALIZED
+		{
+			unsafe { Some(&**self.data.get()) }
+		}
+		else
+		{
+			None
+		}
+	}
+}
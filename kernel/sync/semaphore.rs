@@ -0,0 +1,64 @@
+use core::cell::UnsafeCell;
+use platform::cpu;
+use kernel::thread::WaitQueue;
+
+/// Counting semaphore. `release` may be called from interrupt handlers.
+pub struct Semaphore
+{
+	pub count: UnsafeCell<usize>,
+	pub waiters: WaitQueue
+}
+
+unsafe impl Sync for Semaphore {}
+unsafe impl Send for Semaphore {}
+
+impl Semaphore
+{
+	pub fn new(count: usize) -> Semaphore
+	{
+		Semaphore { count: UnsafeCell::new(count), waiters: WaitQueue::new() }
+	}
+
+	/// Takes one unit, sleeping until one is available
+	pub fn acquire(&self)
+	{
+		loop
+		{
+			// Interrupts stay off between the check and blocking, so a release cannot be missed
+			let flags = cpu::disable_interrupts();
+			if self.try_acquire()
+			{
+				cpu::restore_interrupts(flags);
+				return;
+			}
+			self.waiters.wait();
+			cpu::restore_interrupts(flags);
+		}
+	}
+
+	pub fn try_acquire(&self) -> bool
+	{
+		let flags = cpu::disable_interrupts();
+		let count = unsafe { &mut *self.count.get() };
+		let taken = *count > 0;
+		if taken
+		{
+			*count -= 1;
+		}
+		cpu::restore_interrupts(flags);
+		taken
+	}
+
+	pub fn release(&self)
+	{
+		let flags = cpu::disable_interrupts();
+		unsafe { *self.count.get() += 1; }
+		self.waiters.wake_one();
+		cpu::restore_interrupts(flags);
+	}
+
+	pub fn available(&self) -> usize
+	{
+		unsafe { *self.count.get() }
+	}
+}
@@ -0,0 +1,132 @@
+use core::prelude::*;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+use platform::cpu;
+
+/// Busy-waiting lock around a value
+pub struct Spinlock<T>
+{
+	pub locked: AtomicBool,
+	pub data: UnsafeCell<T>
+}
+
+unsafe impl<T: Send> Sync for Spinlock<T> {}
+unsafe impl<T: Send> Send for Spinlock<T> {}
+
+pub struct SpinlockGuard<'a, T: 'a>
+{
+	lock: &'a Spinlock<T>
+}
+
+impl<T> Spinlock<T>
+{
+	pub fn new(value: T) -> Spinlock<T>
+	{
+		Spinlock { locked: AtomicBool::new(false), data: UnsafeCell::new(value) }
+	}
+
+	pub fn lock(&self) -> SpinlockGuard<T>
+	{
+		while self.locked.compare_and_swap(false, true, Ordering::Acquire)
+		{
+			cpu::relax();
+		}
+		SpinlockGuard { lock: self }
+	}
+
+	pub fn try_lock(&self) -> Option<SpinlockGuard<T>>
+	{
+		if self.locked.compare_and_swap(false, true, Ordering::Acquire)
+		{
+			None
+		}
+		else
+		{
+			Some(SpinlockGuard { lock: self })
+		}
+	}
+
+	/// Accesses the value while ignoring the lock, for crash handlers only
+	pub unsafe fn force_get(&self) -> &mut T
+	{
+		&mut *self.data.get()
+	}
+}
+
+impl<'a, T> Deref for SpinlockGuard<'a, T>
+{
+	type Target = T;
+	fn deref(&self) -> &T { unsafe { &*self.lock.data.get() } }
+}
+
+impl<'a, T> DerefMut for SpinlockGuard<'a, T>
+{
+	fn deref_mut(&mut self) -> &mut T { unsafe { &mut *self.lock.data.get() } }
+}
+
+#[unsafe_destructor]
+impl<'a, T> Drop for SpinlockGuard<'a, T>
+{
+	fn drop(&mut self)
+	{
+		self.lock.locked.store(false, Ordering::Release);
+	}
+}
+
+/// Spinlock that also keeps interrupts disabled while it is held, for data
+/// that interrupt handlers touch as well
+pub struct IrqLock<T>
+{
+	pub inner: Spinlock<T>
+}
+
+unsafe impl<T: Send> Sync for IrqLock<T> {}
+unsafe impl<T: Send> Send for IrqLock<T> {}
+
+pub struct IrqLockGuard<'a, T: 'a>
+{
+	guard: Option<SpinlockGuard<'a, T>>,
+	flags: u32
+}
+
+impl<T> IrqLock<T>
+{
+	pub fn new(value: T) -> IrqLock<T>
+	{
+		IrqLock { inner: Spinlock::new(value) }
+	}
+
+	pub fn lock(&self) -> IrqLockGuard<T>
+	{
+		let flags = cpu::disable_interrupts();
+		IrqLockGuard { guard: Some(self.inner.lock()), flags: flags }
+	}
+
+	pub unsafe fn force_get(&self) -> &mut T
+	{
+		self.inner.force_get()
+	}
+}
+
+impl<'a, T> Deref for IrqLockGuard<'a, T>
+{
+	type Target = T;
+	fn deref(&self) -> &T { self.guard.as_ref().unwrap().deref() }
+}
+
+impl<'a, T> DerefMut for IrqLockGuard<'a, T>
+{
+	fn deref_mut(&mut self) -> &mut T { self.guard.as_mut().unwrap().deref_mut() }
+}
+
+#[unsafe_destructor]
+impl<'a, T> Drop for IrqLockGuard<'a, T>
+{
+	fn drop(&mut self)
+	{
+		// Release the lock before interrupts can come back
+		self.guard = None;
+		cpu::restore_interrupts(self.flags);
+	}
+}
@@ -0,0 +1,462 @@
+/*
+ * System calls
+ *
+ * User code raises int 0x80 with the call number in EAX and up to three
+ * arguments in EBX, ECX and EDX. The result is written back into EAX, errors
+ * are returned as negative numbers.
+ *
+ * The handler runs with interrupts enabled again, so a call that blocks lets
+ * other threads and the timer carry on.
+ *
+ * User memory is only accessed through copy_from_user and copy_to_user, which
+ * check the range and map whatever the page fault handler would map, so the
+ * copies themselves cannot fault.
+ */
+
+use core::prelude::*;
+use core::{cmp, iter, mem, ptr, slice};
+use collections::string::String;
+use collections::vec::Vec;
+use platform::cpu;
+use platform::cpu::InterruptArguments;
+use platform::mmu::paging;
+use kernel::interrupts::pagefault;
+use kernel::{fs, process, thread, time};
+use kernel::fs::{FileType, FsError, SeekFrom};
+use kernel::process::{FileRef, Pid};
+
+pub const SYS_WRITE: u32 = 0;
+pub const SYS_READ: u32 = 1;
+pub const SYS_EXIT: u32 = 2;
+pub const SYS_GETPID: u32 = 3;
+pub const SYS_SLEEP: u32 = 4;
+pub const SYS_UPTIME: u32 = 5;
+pub const SYS_WAIT: u32 = 6;
+pub const SYS_FORK: u32 = 7;
+pub const SYS_OPEN: u32 = 8;
+pub const SYS_CLOSE: u32 = 9;
+pub const SYS_SEEK: u32 = 10;
+pub const SYS_FSTAT: u32 = 11;
+pub const SYS_READDIR: u32 = 12;
+pub const SYS_MKDIR: u32 = 13;
+pub const SYS_UNLINK: u32 = 14;
+pub const SYS_RMDIR: u32 = 15;
+pub const SYS_RENAME: u32 = 16;
+pub const SYS_FTRUNCATE: u32 = 17;
+
+/// Whence values for seek
+pub const SEEK_SET: u32 = 0;
+pub const SEEK_CUR: u32 = 1;
+pub const SEEK_END: u32 = 2;
+
+pub const ENOENT: i32 = 2;
+pub const EINTR: i32 = 4;
+pub const EBADF: i32 = 9;
+pub const ECHILD: i32 = 10;
+pub const ENOMEM: i32 = 12;
+pub const EFAULT: i32 = 14;
+pub const EBUSY: i32 = 16;
+pub const EEXIST: i32 = 17;
+pub const EXDEV: i32 = 18;
+pub const ENOTDIR: i32 = 20;
+pub const EISDIR: i32 = 21;
+pub const EINVAL: i32 = 22;
+pub const EMFILE: i32 = 24;
+pub const ENOSPC: i32 = 28;
+pub const ESPIPE: i32 = 29;
+pub const EROFS: i32 = 30;
+pub const ENOSYS: i32 = 38;
+pub const ENOTEMPTY: i32 = 39;
+
+/// What fstat writes to user memory
+#[repr(C)]
+pub struct UserStat
+{
+	pub inode: u32,
+	/// 0 file, 1 directory, 2 symbolic link, 3 character device, 4 block device
+	pub kind: u32,
+	pub mode: u32,
+	pub size: u32
+}
+
+/// Arguments of rename, which needs more than three registers
+#[repr(C)]
+pub struct UserRename
+{
+	pub from: u32,
+	pub from_length: u32,
+	pub to: u32,
+	pub to_length: u32
+}
+
+type Syscall = fn(u32, u32, u32) -> Result<u32, i32>;
+
+// Reads and writes go through a kernel buffer of at most this size at a time
+const IO_CHUNK_SIZE: u32 = 4096;
+
+// SYS_FORK is handled separately and only has a placeholder here
+static SYSCALLS: [Syscall; 18] = [
+	sys_write,
+	sys_read,
+	sys_exit,
+	sys_getpid,
+	sys_sleep,
+	sys_uptime,
+	sys_wait,
+	sys_nosys,
+	sys_open,
+	sys_close,
+	sys_seek,
+	sys_fstat,
+	sys_readdir,
+	sys_mkdir,
+	sys_unlink,
+	sys_rmdir,
+	sys_rename,
+	sys_ftruncate,
+];
+
+pub fn handle_syscall(args: &mut InterruptArguments)
+{
+	cpu::enable_interrupts();
+
+	// fork copies the whole register frame of the caller, so it is not in the table
+	let result = if args.eax == SYS_FORK
+	{
+		sys_fork(args)
+	}
+	else
+	{
+		match SYSCALLS.get(args.eax as usize)
+		{
+			Some(call) => (*call)(args.ebx, args.ecx, args.edx),
+			None => Err(ENOSYS),
+		}
+	};
+
+	cpu::disable_interrupts();
+	args.eax = match result
+	{
+		Ok(value) => value,
+		Err(errno) => -errno as u32,
+	};
+}
+
+/// write(fd, buffer, length) -> bytes written
+fn sys_write(fd: u32, buffer: u32, length: u32) -> Result<u32, i32>
+{
+	let file = try!(user_file(fd));
+	try!(check_user_range(buffer, length, false));
+	let mut file = file.lock();
+	let mut written = 0;
+	while written < length
+	{
+		let chunk = cmp::min(length - written, IO_CHUNK_SIZE);
+		let bytes = try!(copy_from_user(buffer + written, chunk));
+		let count = match file.write(&bytes)
+		{
+			Ok(count) => count as u32,
+			Err(_) if written > 0 => break,
+			Err(error) => return Err(fs_errno(error)),
+		};
+		written += count;
+		if count < chunk { break }
+	}
+	Ok(written)
+}
+
+/// read(fd, buffer, length) -> bytes read, 0 at the end of a file. Reading
+/// the console blocks until at least one key is typed and stops after a newline.
+fn sys_read(fd: u32, buffer: u32, length: u32) -> Result<u32, i32>
+{
+	let file = try!(user_file(fd));
+	// Checked first, so that nothing is consumed for a bad buffer
+	try!(check_user_range(buffer, length, true));
+	let mut bytes: Vec<u8> = iter::repeat(0).take(cmp::min(length, IO_CHUNK_SIZE) as usize).collect();
+	let count = match file.lock().read(&mut bytes)
+	{
+		Ok(count) => count,
+		Err(error) => return Err(fs_errno(error)),
+	};
+	try!(copy_to_user(buffer, &bytes[.. count]));
+	Ok(count as u32)
+}
+
+/// exit(code), does not return to the caller
+fn sys_exit(code: u32, _: u32, _: u32) -> Result<u32, i32>
+{
+	thread::kill_current(code as usize);
+	Ok(0)
+}
+
+fn sys_getpid(_: u32, _: u32, _: u32) -> Result<u32, i32>
+{
+	let Pid(pid) = process::current_pid();
+	Ok(pid)
+}
+
+/// sleep(milliseconds)
+fn sys_sleep(ms: u32, _: u32, _: u32) -> Result<u32, i32>
+{
+	time::sleep_ms(ms);
+	Ok(0)
+}
+
+/// uptime() -> milliseconds since boot, wrapping after 49 days
+fn sys_uptime(_: u32, _: u32, _: u32) -> Result<u32, i32>
+{
+	Ok(time::uptime() as u32)
+}
+
+/// wait(pid) -> exit code of the child
+fn sys_wait(pid: u32, _: u32, _: u32) -> Result<u32, i32>
+{
+	match process::wait(Pid(pid))
+	{
+		Some(code) => Ok(code as u32),
+		None => Err(ECHILD),
+	}
+}
+
+/// fork() -> PID of the child in the parent, 0 in the child
+fn sys_fork(args: &InterruptArguments) -> Result<u32, i32>
+{
+	match process::fork(args)
+	{
+		Some(Pid(pid)) => Ok(pid),
+		None => Err(ENOMEM),
+	}
+}
+
+fn sys_nosys(_: u32, _: u32, _: u32) -> Result<u32, i32>
+{
+	Err(ENOSYS)
+}
+
+/// open(path, path length, flags) -> fd, with the fs::OPEN_ flags
+fn sys_open(path: u32, length: u32, flags: u32) -> Result<u32, i32>
+{
+	let path = try!(user_path(path, length));
+	let file = match fs::open(&path, flags)
+	{
+		Ok(file) => file,
+		Err(error) => return Err(fs_errno(error)),
+	};
+	match process::add_file(file)
+	{
+		Some(fd) => Ok(fd),
+		None => Err(EMFILE),
+	}
+}
+
+/// close(fd)
+fn sys_close(fd: u32, _: u32, _: u32) -> Result<u32, i32>
+{
+	if process::close_file(fd) { Ok(0) } else { Err(EBADF) }
+}
+
+/// seek(fd, offset, whence) -> new position. The offset is signed.
+fn sys_seek(fd: u32, offset: u32, whence: u32) -> Result<u32, i32>
+{
+	let file = try!(user_file(fd));
+	let position = match whence
+	{
+		SEEK_SET => SeekFrom::Start(offset as u64),
+		SEEK_CUR => SeekFrom::Current(offset as i32 as i64),
+		SEEK_END => SeekFrom::End(offset as i32 as i64),
+		_ => return Err(EINVAL),
+	};
+	let mut file = file.lock();
+	match file.seek(position)
+	{
+		Ok(position) => Ok(position as u32),
+		Err(FsError::Unsupported) => Err(ESPIPE),
+		Err(error) => Err(fs_errno(error)),
+	}
+}
+
+/// fstat(fd, buffer) fills a UserStat
+fn sys_fstat(fd: u32, buffer: u32, _: u32) -> Result<u32, i32>
+{
+	let file = try!(user_file(fd));
+	let stat = file.lock().stat();
+	let user_stat = UserStat
+	{
+		inode: stat.inode as u32,
+		kind: match stat.kind
+		{
+			FileType::File => 0,
+			FileType::Directory => 1,
+			FileType::Symlink => 2,
+			FileType::CharDevice => 3,
+			FileType::BlockDevice => 4,
+		},
+		mode: stat.mode,
+		size: stat.size as u32
+	};
+	// Copied byte by byte, the buffer does not have to be aligned
+	let bytes = unsafe { slice::from_raw_parts(&user_stat as *const UserStat as *const u8, mem::size_of::<UserStat>()) };
+	try!(copy_to_user(buffer, bytes));
+	Ok(0)
+}
+
+/// readdir(fd, buffer, length) -> length of the name of the next entry of a
+/// directory, 0 after the last one. Longer names are cut off.
+fn sys_readdir(fd: u32, buffer: u32, length: u32) -> Result<u32, i32>
+{
+	let file = try!(user_file(fd));
+	try!(check_user_range(buffer, length, true));
+	let entry = match file.lock().readdir()
+	{
+		Ok(Some(entry)) => entry,
+		Ok(None) => return Ok(0),
+		Err(error) => return Err(fs_errno(error)),
+	};
+	let name = entry.name.as_bytes();
+	let count = cmp::min(name.len(), length as usize);
+	try!(copy_to_user(buffer, &name[.. count]));
+	Ok(count as u32)
+}
+
+/// mkdir(path, path length, mode)
+fn sys_mkdir(path: u32, length: u32, mode: u32) -> Result<u32, i32>
+{
+	let path = try!(user_path(path, length));
+	fs_result(fs::mkdir(&path, mode))
+}
+
+/// unlink(path, path length)
+fn sys_unlink(path: u32, length: u32, _: u32) -> Result<u32, i32>
+{
+	let path = try!(user_path(path, length));
+	fs_result(fs::unlink(&path))
+}
+
+/// rmdir(path, path length)
+fn sys_rmdir(path: u32, length: u32, _: u32) -> Result<u32, i32>
+{
+	let path = try!(user_path(path, length));
+	fs_result(fs::rmdir(&path))
+}
+
+/// rename(arguments) with the paths in a UserRename
+fn sys_rename(arguments: u32, _: u32, _: u32) -> Result<u32, i32>
+{
+	let bytes = try!(copy_from_user(arguments, mem::size_of::<UserRename>() as u32));
+	let mut arguments = UserRename { from: 0, from_length: 0, to: 0, to_length: 0 };
+	unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), &mut arguments as *mut UserRename as *mut u8, bytes.len()); }
+	let from = try!(user_path(arguments.from, arguments.from_length));
+	let to = try!(user_path(arguments.to, arguments.to_length));
+	fs_result(fs::rename(&from, &to))
+}
+
+/// ftruncate(fd, size)
+fn sys_ftruncate(fd: u32, size: u32, _: u32) -> Result<u32, i32>
+{
+	let file = try!(user_file(fd));
+	let mut file = file.lock();
+	fs_result(file.truncate(size as u64))
+}
+
+fn user_path(path: u32, length: u32) -> Result<String, i32>
+{
+	let bytes = try!(copy_from_user(path, length));
+	match String::from_utf8(bytes)
+	{
+		Ok(path) => Ok(path),
+		Err(_) => Err(EINVAL),
+	}
+}
+
+fn fs_result(result: Result<(), FsError>) -> Result<u32, i32>
+{
+	match result
+	{
+		Ok(()) => Ok(0),
+		Err(error) => Err(fs_errno(error)),
+	}
+}
+
+fn user_file(fd: u32) -> Result<FileRef, i32>
+{
+	match process::file(fd)
+	{
+		Some(file) => Ok(file),
+		None => Err(EBADF),
+	}
+}
+
+fn fs_errno(error: FsError) -> i32
+{
+	match error
+	{
+		FsError::NotFound => ENOENT,
+		FsError::NotDirectory => ENOTDIR,
+		FsError::IsDirectory => EISDIR,
+		FsError::ReadOnly => EROFS,
+		FsError::WrongMode => EBADF,
+		FsError::InvalidArgument => EINVAL,
+		FsError::Busy => EBUSY,
+		FsError::Unsupported => ENOSYS,
+		FsError::Exists => EEXIST,
+		FsError::NotEmpty => ENOTEMPTY,
+		FsError::CrossDevice => EXDEV,
+		FsError::Interrupted => EINTR,
+		FsError::NoSpace => ENOSPC,
+	}
+}
+
+/// Checks that [address, address + length) is mapped for user code, and
+/// writable too when `write` is set. Pages the page fault handler would map
+/// or copy are mapped or copied here, so accessing the range cannot fault.
+fn check_user_range(address: u32, length: u32, write: bool) -> Result<(), i32>
+{
+	if length == 0 { return Ok(()) }
+	let end = match address.checked_add(length) { Some(end) => end, None => return Err(EFAULT) };
+
+	let mut page = address & !(paging::PAGE_SIZE - 1);
+	while page < end
+	{
+		if paging::page_entry(page).is_none() && !pagefault::map_demand_page(page, true)
+		{
+			return Err(EFAULT);
+		}
+		match paging::page_entry(page)
+		{
+			Some(entry) if entry & paging::USER == 0 => return Err(EFAULT),
+			Some(entry) if !write || entry & paging::WRITABLE != 0 => {},
+			Some(entry) if entry & paging::COPY_ON_WRITE != 0 =>
+			{
+				if !paging::resolve_copy_on_write(page) { return Err(ENOMEM) }
+			},
+			_ => return Err(EFAULT),
+		}
+		page = match page.checked_add(paging::PAGE_SIZE) { Some(p) => p, None => break };
+	}
+	Ok(())
+}
+
+/// Copies `length` bytes of user memory at `address` into a kernel buffer
+fn copy_from_user(address: u32, length: u32) -> Result<Vec<u8>, i32>
+{
+	try!(check_user_range(address, length, false));
+	let mut bytes = Vec::with_capacity(length as usize);
+	unsafe
+	{
+		bytes.set_len(length as usize);
+		if length > 0 { ptr::copy_nonoverlapping(address as *const u8, bytes.as_mut_ptr(), length as usize); }
+	}
+	Ok(bytes)
+}
+
+/// Copies `data` to user memory at `address`
+fn copy_to_user(address: u32, data: &[u8]) -> Result<(), i32>
+{
+	try!(check_user_range(address, data.len() as u32, true));
+	if data.len() > 0
+	{
+		unsafe { ptr::copy_nonoverlapping(data.as_ptr(), address as *mut u8, data.len()); }
+	}
+	Ok(())
+}
+
@@ -0,0 +1,650 @@
+/*
+ * Kernel threads
+ *
+ * Every thread owns a kernel stack. A thread that is not running is fully
+ * described by the context isr_common_stub saved on that stack, so switching
+ * threads only means returning another context from isr_handler. This happens
+ * on every timer tick and whenever a thread yields or blocks.
+ *
+ * The runnable thread with the highest priority runs, threads of equal
+ * priority share the CPU round robin. An idle thread at the lowest priority
+ * makes sure there is always something to run.
+ *
+ * User threads run in ring 3 and enter the kernel only through interrupts,
+ * which the CPU delivers on the thread's kernel stack as named by the TSS.
+ *
+ * A finished thread keeps its stack until it is joined. Every thread that
+ * finishes wakes the FINISHED wait queue, which `join` and
+ * `wait_for_finished` block on.
+ */
+
+use core::prelude::*;
+use core::mem;
+use alloc::boxed::Box;
+use collections::vec::Vec;
+use platform::cpu;
+use platform::cpu::InterruptArguments;
+use platform::cpu::context;
+use platform::cpu::context::Context;
+use platform::mmu::paging;
+use kernel::heap;
+use kernel::time;
+
+pub use self::waitqueue::{WaitQueue, WAIT_QUEUE_INIT};
+
+pub mod waitqueue;
+
+pub type ThreadEntry = fn(usize) -> usize;
+
+const STACK_SIZE: usize = 16384;
+const STACK_ALIGN: usize = 16;
+
+pub struct ThreadId(pub u32);
+
+impl Copy for ThreadId {}
+impl Clone for ThreadId { fn clone(&self) -> Self { *self } }
+
+pub enum Priority
+{
+	Idle = 0,
+	Low = 1,
+	Normal = 2,
+	High = 3,
+}
+
+impl Copy for Priority {}
+impl Clone for Priority { fn clone(&self) -> Self { *self } }
+
+pub enum State
+{
+	Ready,
+	Running,
+	/// Waiting for the tick count to reach the deadline
+	Sleeping(u64),
+	/// Waiting on the wait queue at this address
+	Blocked(usize),
+	Finished(usize),
+}
+
+impl Copy for State {}
+impl Clone for State { fn clone(&self) -> Self { *self } }
+
+pub struct ThreadInfo
+{
+	pub id: ThreadId,
+	pub priority: Priority,
+	pub state: State,
+	pub cpu_ticks: u64
+}
+
+impl Copy for ThreadInfo {}
+impl Clone for ThreadInfo { fn clone(&self) -> Self { *self } }
+
+struct Thread
+{
+	id: u32,
+	state: State,
+	priority: Priority,
+	context: *mut Context,
+	// Null for the boot thread, which runs on the bootstrap stack
+	stack: *mut u8,
+	// Page directory loaded while the thread runs
+	directory: u32,
+	entry: Option<ThreadEntry>,
+	arg: usize,
+	cpu_ticks: u64,
+	// Orders the waiters of a wait queue
+	blocked_at: u64,
+	// Exit code of a pending `kill`
+	kill_code: Option<usize>
+}
+
+struct Scheduler
+{
+	threads: Vec<Box<Thread>>,
+	current: usize,
+	next_id: u32,
+	need_switch: bool,
+	block_counter: u64,
+	finished_count: u64
+}
+
+// Not behind a lock: a thread switch happens in the middle of scheduler code,
+// so no guard could be released by the thread that took it. Every access runs
+// with interrupts disabled instead, which is enough on a single CPU.
+static mut scheduler: *mut Scheduler = 0 as *mut Scheduler;
+
+static FINISHED: WaitQueue = WAIT_QUEUE_INIT;
+
+/// Turns the code that is running now into thread 0 and starts the idle thread
+pub fn init()
+{
+	let boot = Box::new(Thread
+	{
+		id: 0,
+		state: State::Running,
+		priority: Priority::Normal,
+		context: 0 as *mut Context,
+		stack: 0 as *mut u8,
+		directory: paging::kernel_directory_address(),
+		entry: None,
+		arg: 0,
+		cpu_ticks: 0,
+		blocked_at: 0,
+		kill_code: None
+	});
+
+	let mut threads = Vec::new();
+	threads.push(boot);
+	let s = Box::new(Scheduler { threads: threads, current: 0, next_id: 1, need_switch: false, block_counter: 0, finished_count: 0 });
+	unsafe { scheduler = mem::transmute(s); }
+
+	spawn_with_priority(idle_thread, 0, Priority::Idle);
+}
+
+pub fn is_running() -> bool
+{
+	unsafe { !scheduler.is_null() }
+}
+
+/// Starts a thread running `entry(arg)`. Its return value becomes the exit code.
+pub fn spawn(entry: ThreadEntry, arg: usize) -> ThreadId
+{
+	spawn_with_priority(entry, arg, Priority::Normal)
+}
+
+pub fn spawn_with_priority(entry: ThreadEntry, arg: usize, priority: Priority) -> ThreadId
+{
+	let stack = allocate_stack();
+	let context = context::new_context(stack as u32 + STACK_SIZE as u32, thread_start as u32);
+	add_thread(stack, context, paging::kernel_directory_address(), Some(entry), arg, priority)
+}
+
+/// Starts a thread that runs in ring 3 from `entry` with the stack pointer at
+/// `user_stack`, in the address space of page directory `directory`. Both
+/// addresses have to be mapped with paging::USER there. The address space is
+/// destroyed when the thread is joined.
+pub fn spawn_user(entry: u32, user_stack: u32, directory: u32) -> ThreadId
+{
+	let stack = allocate_stack();
+	let context = context::new_user_context(stack as u32 + STACK_SIZE as u32, entry, user_stack);
+	add_thread(stack, context, directory, None, 0, Priority::Normal)
+}
+
+/// Starts a user thread in the address space `directory` that resumes with the
+/// registers and FPU state of the interrupt `frame`, which must come from ring
+/// 3, except that EAX holds `eax`
+pub fn spawn_user_from_frame(frame: &InterruptArguments, eax: u32, directory: u32) -> ThreadId
+{
+	let stack = allocate_stack();
+	let context = context::new_context_from_frame(stack as u32 + STACK_SIZE as u32, frame, eax);
+	add_thread(stack, context, directory, None, 0, Priority::Normal)
+}
+
+fn allocate_stack() -> *mut u8
+{
+	let stack = heap::allocate(STACK_SIZE, STACK_ALIGN);
+	// Heap pages are mapped on first touch, but a fault on a stack page that
+	// is missing cannot be delivered and turns into a double fault
+	if stack.is_null() || !heap::populate(stack, STACK_SIZE)
+	{
+		panic!("Out of memory for a thread stack");
+	}
+	stack
+}
+
+fn add_thread(stack: *mut u8, context: *mut Context, directory: u32, entry: Option<ThreadEntry>, arg: usize, priority: Priority) -> ThreadId
+{
+	let flags = cpu::disable_interrupts();
+	let s = get();
+	let id = s.next_id;
+	s.next_id += 1;
+	s.threads.push(Box::new(Thread
+	{
+		id: id,
+		state: State::Ready,
+		priority: priority,
+		context: context,
+		stack: stack,
+		directory: directory,
+		entry: entry,
+		arg: arg,
+		cpu_ticks: 0,
+		blocked_at: 0,
+		kill_code: None
+	}));
+	preempt_for(priority);
+	cpu::restore_interrupts(flags);
+
+	ThreadId(id)
+}
+
+pub fn current_id() -> ThreadId
+{
+	let s = get();
+	ThreadId(s.threads[s.current].id)
+}
+
+pub fn set_priority(priority: Priority)
+{
+	let flags = cpu::disable_interrupts();
+	{
+		let s = get();
+		s.threads[s.current].priority = priority;
+	}
+	request_switch();
+	cpu::restore_interrupts(flags);
+}
+
+/// Gives up the rest of the time slice
+pub fn yield_now()
+{
+	request_switch();
+	cpu::yield_interrupt();
+}
+
+/// Blocks the current thread until the tick count reaches `deadline`, or until
+/// it is killed
+pub fn sleep_until(deadline: u64)
+{
+	let flags = cpu::disable_interrupts();
+	if time::ticks() < deadline
+	{
+		{
+			let s = get();
+			s.threads[s.current].state = State::Sleeping(deadline);
+		}
+		yield_now();
+	}
+	cpu::restore_interrupts(flags);
+}
+
+pub fn sleep_ms(ms: u32)
+{
+	sleep_until(time::ticks() + time::ms_to_ticks(ms) as u64);
+}
+
+/// Ends the current thread. `join` hands `code` to whoever waits for it.
+pub fn exit(code: usize) -> !
+{
+	cpu::disable_interrupts();
+	{
+		let s = get();
+		let current = s.current;
+		finish(s, current, code);
+	}
+	loop
+	{
+		yield_now();
+	}
+}
+
+/// Loads the page directory `directory` for the current thread and returns the
+/// one it used before. Lets kernel code fill in another address space.
+pub fn set_address_space(directory: u32) -> u32
+{
+	let flags = cpu::disable_interrupts();
+	let previous = {
+		let s = get();
+		let current = s.current;
+		let previous = s.threads[current].directory;
+		s.threads[current].directory = directory;
+		previous
+	};
+	paging::switch_directory(directory);
+	cpu::restore_interrupts(flags);
+	previous
+}
+
+/// Ends the current thread from an interrupt handler, for example when user
+/// code faults. The interrupted context is dropped on the way out of the
+/// interrupt and never resumed.
+pub fn kill_current(code: usize)
+{
+	let s = get();
+	let current = s.current;
+	finish(s, current, code);
+	s.need_switch = true;
+}
+
+/// Ends another thread with exit code `code`. A thread that is in the middle of
+/// a system call finishes it first and dies on its way back to user mode, so
+/// it cannot take kernel locks down with it. If it is sleeping or blocked it is
+/// woken, and waits that check `interrupted` give up. Kernel threads cannot be
+/// killed.
+pub fn kill(id: ThreadId, code: usize) -> bool
+{
+	let ThreadId(id) = id;
+	let flags = cpu::disable_interrupts();
+	let s = get();
+	let killed = match s.threads.iter().position(|t| t.id == id)
+	{
+		Some(index) if index != s.current && !s.threads[index].stack.is_null() =>
+		{
+			let state = s.threads[index].state;
+			match state
+			{
+				State::Finished(_) => false,
+				_ if in_user_mode(s.threads[index].context) => { finish(s, index, code); true },
+				_ if s.threads[index].directory != paging::kernel_directory_address() =>
+				{
+					let thread = &mut s.threads[index];
+					thread.kill_code = Some(code);
+					match thread.state
+					{
+						State::Sleeping(_) | State::Blocked(_) => thread.state = State::Ready,
+						_ => {},
+					}
+					true
+				},
+				_ => false,
+			}
+		},
+		_ => false,
+	};
+	cpu::restore_interrupts(flags);
+	killed
+}
+
+/// Whether the current thread has been killed and should leave the system call
+/// it is in. Blocking waits that may take long check this after waking up.
+pub fn interrupted() -> bool
+{
+	let flags = cpu::disable_interrupts();
+	let result = {
+		let s = get();
+		s.threads[s.current].kill_code.is_some()
+	};
+	cpu::restore_interrupts(flags);
+	result
+}
+
+fn in_user_mode(context: *mut Context) -> bool
+{
+	unsafe { (*(*context).args).from_user_mode() }
+}
+
+/// Marks the thread at `index` finished and wakes whoever waits for it.
+/// Interrupts must be disabled.
+fn finish(s: &mut Scheduler, index: usize, code: usize)
+{
+	s.threads[index].state = State::Finished(code);
+	s.finished_count += 1;
+	FINISHED.wake_all();
+}
+
+/// Whether the thread has finished and waits to be joined
+pub fn is_finished(id: ThreadId) -> bool
+{
+	let ThreadId(id) = id;
+	let flags = cpu::disable_interrupts();
+	let result = match get().threads.iter().find(|t| t.id == id)
+	{
+		Some(thread) => match thread.state { State::Finished(_) => true, _ => false },
+		None => false,
+	};
+	cpu::restore_interrupts(flags);
+	result
+}
+
+/// Number of threads that have finished since boot
+pub fn finished_count() -> u64
+{
+	let flags = cpu::disable_interrupts();
+	let count = get().finished_count;
+	cpu::restore_interrupts(flags);
+	count
+}
+
+/// Blocks until more than `seen` threads have finished since boot, see
+/// `finished_count`, or until the calling thread is killed
+pub fn wait_for_finished(seen: u64)
+{
+	let flags = cpu::disable_interrupts();
+	if get().finished_count <= seen && !interrupted()
+	{
+		FINISHED.wait();
+	}
+	cpu::restore_interrupts(flags);
+}
+
+/// Waits for a thread to finish, frees it and returns its exit code.
+/// Returns None for unknown threads, for the calling thread itself and when
+/// the calling thread is killed while waiting.
+pub fn join(id: ThreadId) -> Option<usize>
+{
+	let ThreadId(id) = id;
+	let flags = cpu::disable_interrupts();
+	loop
+	{
+		let s = get();
+		let index = match s.threads.iter().position(|t| t.id == id)
+		{
+			Some(i) if i != s.current => i,
+			_ => { cpu::restore_interrupts(flags); return None; },
+		};
+
+		if let State::Finished(code) = s.threads[index].state
+		{
+			let thread = s.threads.remove(index);
+			if index < s.current { s.current -= 1; }
+			heap::deallocate(thread.stack, STACK_SIZE);
+			if thread.directory != paging::kernel_directory_address()
+			{
+				paging::destroy_address_space(thread.directory);
+			}
+			cpu::restore_interrupts(flags);
+			return Some(code);
+		}
+
+		if interrupted() { cpu::restore_interrupts(flags); return None; }
+		// Checked and blocked with interrupts disabled, so the wake-up of a
+		// thread finishing in between is not lost
+		FINISHED.wait();
+	}
+}
+
+/// Snapshot of every thread, for diagnostics
+pub fn list() -> Vec<ThreadInfo>
+{
+	let flags = cpu::disable_interrupts();
+	let result = get().threads.iter().map(|t| ThreadInfo
+	{
+		id: ThreadId(t.id),
+		priority: t.priority,
+		state: t.state,
+		cpu_ticks: t.cpu_ticks
+	}).collect();
+	cpu::restore_interrupts(flags);
+	result
+}
+
+/// Called from the timer interrupt: charges the tick to the running thread,
+/// wakes sleepers and ends the time slice
+pub fn tick()
+{
+	if !is_running() { return }
+
+	let now = time::ticks();
+	let s = get();
+	s.threads[s.current].cpu_ticks += 1;
+
+	for thread in s.threads.iter_mut()
+	{
+		if let State::Sleeping(deadline) = thread.state
+		{
+			if deadline <= now
+			{
+				thread.state = State::Ready;
+			}
+		}
+	}
+	s.need_switch = true;
+}
+
+/// Asks for a thread switch on the way out of the current interrupt
+pub fn request_switch()
+{
+	unsafe
+	{
+		if !scheduler.is_null()
+		{
+			(*scheduler).need_switch = true;
+		}
+	}
+}
+
+/// Puts the current thread to sleep on the wait queue at `queue`. Interrupts
+/// must be disabled, so that a wake-up cannot slip in before the thread blocks.
+fn block_on(queue: usize)
+{
+	{
+		let s = get();
+		s.block_counter += 1;
+		let current = s.current;
+		s.threads[current].state = State::Blocked(queue);
+		s.threads[current].blocked_at = s.block_counter;
+	}
+	yield_now();
+}
+
+/// Makes the longest waiting thread blocked on `queue` runnable
+fn wake_one(queue: usize) -> bool
+{
+	let flags = cpu::disable_interrupts();
+	let s = get();
+	let mut oldest: Option<usize> = None;
+	for (index, thread) in s.threads.iter().enumerate()
+	{
+		if let State::Blocked(q) = thread.state
+		{
+			if q == queue && oldest.map_or(true, |o| thread.blocked_at < s.threads[o].blocked_at)
+			{
+				oldest = Some(index);
+			}
+		}
+	}
+
+	let woken = match oldest
+	{
+		Some(index) =>
+		{
+			s.threads[index].state = State::Ready;
+			let priority = s.threads[index].priority;
+			preempt_for(priority);
+			true
+		},
+		None => false,
+	};
+	cpu::restore_interrupts(flags);
+	woken
+}
+
+fn wake_all(queue: usize) -> usize
+{
+	let mut count = 0;
+	while wake_one(queue) { count += 1; }
+	count
+}
+
+/// Requests a switch if a thread of `priority` should run instead of the current one
+fn preempt_for(priority: Priority)
+{
+	let s = get();
+	if priority as u32 > s.threads[s.current].priority as u32
+	{
+		s.need_switch = true;
+	}
+}
+
+/// Called by isr_handler with the context of the interrupted thread. Returns
+/// the context of the thread that should run next.
+pub fn switch_if_needed(context: *mut Context) -> *mut Context
+{
+	if !is_running() { return context }
+
+	let s = get();
+	if let Some(code) = s.threads[s.current].kill_code
+	{
+		if in_user_mode(context)
+		{
+			let current = s.current;
+			finish(s, current, code);
+			s.need_switch = true;
+		}
+	}
+	if !s.need_switch { return context }
+	s.need_switch = false;
+
+	s.threads[s.current].context = context;
+	if let State::Running = s.threads[s.current].state
+	{
+		s.threads[s.current].state = State::Ready;
+	}
+
+	// Highest priority first, round robin between equals starting after the current thread
+	let count = s.threads.len();
+	let mut best: Option<usize> = None;
+	for offset in (1 .. count + 1)
+	{
+		let index = (s.current + offset) % count;
+		if let State::Ready = s.threads[index].state
+		{
+			let priority = s.threads[index].priority as u32;
+			if best.map_or(true, |b| priority > s.threads[b].priority as u32)
+			{
+				best = Some(index);
+			}
+		}
+	}
+
+	match best
+	{
+		Some(index) =>
+		{
+			s.threads[index].state = State::Running;
+			s.current = index;
+			if !s.threads[index].stack.is_null()
+			{
+				cpu::set_kernel_stack(context::stack_top(s.threads[index].stack as u32 + STACK_SIZE as u32));
+			}
+			paging::switch_directory(s.threads[index].directory);
+			s.threads[index].context
+		},
+		None => panic!("No runnable thread, not even the idle thread"),
+	}
+}
+
+fn idle_thread(_: usize) -> usize
+{
+	loop
+	{
+		cpu::idle();
+	}
+}
+
+/// First code every spawned thread runs
+extern "C" fn thread_start() -> !
+{
+	let (entry, arg) = {
+		let s = get();
+		let thread = &s.threads[s.current];
+		(thread.entry, thread.arg)
+	};
+
+	let code = match entry
+	{
+		Some(f) => f(arg),
+		None => 0,
+	};
+	exit(code);
+}
+
+fn get() -> &'static mut Scheduler
+{
+	unsafe
+	{
+		if scheduler.is_null() { panic!("Threads used before thread::init"); }
+		&mut *scheduler
+	}
+}
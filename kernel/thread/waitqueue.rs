@@ -0,0 +1,51 @@
+/*
+ * Wait queues
+ *
+ * A wait queue holds no list of its own. Blocked threads remember the address
+ * of the queue they wait on, which lets a queue live in a static.
+ */
+
+use platform::cpu;
+
+pub struct WaitQueue
+{
+	// Keeps the queue from being zero sized, so every queue has its own address
+	_unused: u8
+}
+
+pub const WAIT_QUEUE_INIT: WaitQueue = WaitQueue { _unused: 0 };
+
+impl WaitQueue
+{
+	pub fn new() -> WaitQueue
+	{
+		WAIT_QUEUE_INIT
+	}
+
+	/// Blocks until another thread or an interrupt handler wakes the queue.
+	/// Check the condition you wait for with interrupts disabled and call this
+	/// before enabling them again, otherwise a wake-up can get lost.
+	pub fn wait(&self)
+	{
+		let flags = cpu::disable_interrupts();
+		super::block_on(self.key());
+		cpu::restore_interrupts(flags);
+	}
+
+	/// Wakes the thread that has been waiting longest
+	pub fn wake_one(&self) -> bool
+	{
+		super::wake_one(self.key())
+	}
+
+	/// Wakes every waiting thread and returns how many there were
+	pub fn wake_all(&self) -> usize
+	{
+		super::wake_all(self.key())
+	}
+
+	fn key(&self) -> usize
+	{
+		self as *const WaitQueue as usize
+	}
+}
@@ -0,0 +1,133 @@
+/*
+ * Wall-clock time
+ *
+ * The RTC is read once at boot. After that the time is advanced by the uptime
+ * counter, so reading it does not involve any port I/O.
+ */
+
+use core::prelude::*;
+use core::fmt;
+use platform::rtc;
+use kernel::time;
+use kernel::sync::IrqLock;
+
+const SECONDS_PER_DAY: u32 = 86400;
+
+pub struct DateTime
+{
+	pub year: u16,
+	pub month: u8,
+	pub day: u8,
+	pub hour: u8,
+	pub minute: u8,
+	pub second: u8
+}
+
+impl Copy for DateTime {}
+impl Clone for DateTime { fn clone(&self) -> Self { *self } }
+
+impl DateTime
+{
+	/// Breaks a UNIX timestamp down into a date and time (UTC)
+	pub fn from_timestamp(timestamp: u64) -> DateTime
+	{
+		// Timestamps fit in 32 bits until 2106, which keeps the division cheap
+		let timestamp = timestamp as u32;
+		let days = timestamp / SECONDS_PER_DAY;
+		let seconds = timestamp % SECONDS_PER_DAY;
+		let (year, month, day) = civil_from_days(days);
+
+		DateTime
+		{
+			year: year as u16,
+			month: month as u8,
+			day: day as u8,
+			hour: (seconds / 3600) as u8,
+			minute: (seconds / 60 % 60) as u8,
+			second: (seconds % 60) as u8
+		}
+	}
+
+	/// Whether every field is in range and the date is not before 1970, which
+	/// `timestamp` needs
+	pub fn is_valid(&self) -> bool
+	{
+		self.year >= 1970 && self.month >= 1 && self.month <= 12 && self.day >= 1 && self.day <= 31
+			&& self.hour < 24 && self.minute < 60 && self.second < 60
+	}
+
+	pub fn timestamp(&self) -> u64
+	{
+		let days = days_from_civil(self.year as u32, self.month as u32, self.day as u32);
+		days as u64 * SECONDS_PER_DAY as u64
+			+ self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64
+	}
+}
+
+impl fmt::Display for DateTime
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}", self.year, self.month, self.day, self.hour, self.minute, self.second)
+	}
+}
+
+static BOOT_TIMESTAMP: IrqLock<u64> = irq_lock!(0);
+
+pub fn init()
+{
+	let now = rtc::read();
+	let date = DateTime
+	{
+		year: now.year,
+		month: now.month,
+		day: now.day,
+		hour: now.hour,
+		minute: now.minute,
+		second: now.second
+	};
+	// An RTC that was never set can hold anything, even day 0
+	if !date.is_valid()
+	{
+		log!(Warning, "RTC time {} is invalid, starting the clock at 1970-01-01", date);
+		*BOOT_TIMESTAMP.lock() = 0;
+		return;
+	}
+	*BOOT_TIMESTAMP.lock() = date.timestamp().saturating_sub(time::uptime_seconds());
+}
+
+/// Seconds since 1970-01-01 00:00:00 UTC, assuming the RTC runs on UTC
+pub fn now() -> u64
+{
+	*BOOT_TIMESTAMP.lock() + time::uptime_seconds()
+}
+
+pub fn now_datetime() -> DateTime
+{
+	DateTime::from_timestamp(now())
+}
+
+/// Days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: u32, month: u32, day: u32) -> u32
+{
+	let year = if month <= 2 { year - 1 } else { year };
+	let era = year / 400;
+	let year_of_era = year - era * 400;
+	let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+	let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+	era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: u32) -> (u32, u32, u32)
+{
+	let days = days + 719468;
+	let era = days / 146097;
+	let day_of_era = days - era * 146097;
+	let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let mp = (5 * day_of_year + 2) / 153;
+	let day = day_of_year - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+	(year, month, day)
+}
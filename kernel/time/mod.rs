@@ -0,0 +1,147 @@
+/*
+ * Monotonic time driven by the PIT interrupt
+ *
+ * Milliseconds are accumulated on every tick with 32-bit arithmetic, so that
+ * nothing here needs 64-bit division support from the compiler runtime.
+ */
+
+use platform::cpu::timer;
+use kernel::sync::IrqLock;
+use kernel::cmdline;
+use kernel::cmdline::{Parameter, Kind};
+
+pub mod wheel;
+pub mod clock;
+
+struct Counters
+{
+	ticks: u64,
+	uptime_ms: u64,
+	ms_remainder: u32,
+	uptime_s: u64,
+	second_remainder: u32
+}
+
+// The lock also keeps readers from seeing a 64-bit counter halfway updated
+static COUNTERS: IrqLock<Counters> = irq_lock!(Counters { ticks: 0, uptime_ms: 0, ms_remainder: 0, uptime_s: 0, second_remainder: 0 });
+
+const DEFAULT_FREQUENCY: u32 = 50;
+// The PIT divisor has 16 bits, and above 1kHz ticks get shorter than a millisecond
+const MIN_FREQUENCY: u32 = 19;
+const MAX_FREQUENCY: u32 = 1000;
+
+pub static TIMER_HZ: Parameter = Parameter { name: "timer_hz", kind: Kind::Number, description: "timer interrupts per second, 19 to 1000" };
+
+/// Starts the timer, needs the boot information for the command line
+pub fn init()
+{
+	cmdline::register(&TIMER_HZ);
+	let frequency = match TIMER_HZ.number()
+	{
+		Some(hz) if hz >= MIN_FREQUENCY && hz <= MAX_FREQUENCY => hz,
+		Some(hz) =>
+		{
+			log!(Warning, "timer_hz={} is out of range, using {}", hz, DEFAULT_FREQUENCY);
+			DEFAULT_FREQUENCY
+		},
+		None => DEFAULT_FREQUENCY,
+	};
+	timer::set_interval(frequency);
+
+	wheel::init();
+	clock::init();
+}
+
+/// Called from the timer interrupt
+pub fn tick()
+{
+	let frequency = timer::frequency();
+	let mut c = COUNTERS.lock();
+	c.ticks += 1;
+	c.ms_remainder += 1000;
+	let elapsed = c.ms_remainder / frequency;
+	c.uptime_ms += elapsed as u64;
+	c.ms_remainder %= frequency;
+
+	c.second_remainder += elapsed;
+	if c.second_remainder >= 1000
+	{
+		c.uptime_s += 1;
+		c.second_remainder -= 1000;
+	}
+}
+
+/// Number of timer interrupts since boot
+pub fn ticks() -> u64
+{
+	COUNTERS.lock().ticks
+}
+
+/// Milliseconds since boot
+pub fn uptime() -> u64
+{
+	COUNTERS.lock().uptime_ms
+}
+
+/// Whole seconds since boot
+pub fn uptime_seconds() -> u64
+{
+	COUNTERS.lock().uptime_s
+}
+
+pub fn frequency() -> u32
+{
+	timer::frequency()
+}
+
+/// Length of `ticks` timer interrupts in milliseconds
+pub fn ticks_to_ms(ticks: u32) -> u32
+{
+	let frequency = frequency();
+	ticks / frequency * 1000 + ticks % frequency * 1000 / frequency
+}
+
+/// Number of timer interrupts covering at least `ms` milliseconds
+pub fn ms_to_ticks(ms: u32) -> u32
+{
+	let frequency = frequency();
+	ms / 1000 * frequency + (ms % 1000 * frequency + 999) / 1000
+}
+
+/// Waits for at least `ms` milliseconds. Once threads are up other threads run
+/// in the meantime, before that the CPU halts between ticks.
+/// Interrupts have to be enabled.
+pub fn sleep_ms(ms: u32)
+{
+	if ::kernel::thread::is_running()
+	{
+		::kernel::thread::sleep_ms(ms);
+		return;
+	}
+
+	let deadline = uptime() + ms as u64;
+	while uptime() < deadline
+	{
+		::platform::cpu::idle();
+	}
+}
+
+/// Spins for at least `us` microseconds by watching the PIT counter, for
+/// hardware timeouts that are shorter than a tick
+pub fn delay_us(us: u32)
+{
+	let divisor = timer::divisor();
+	let per_ms = timer::BASE_FREQUENCY / 1000;
+	let mut remaining = us / 1000 * per_ms + us % 1000 * per_ms / 1000 + 1;
+	let mut last = timer::read_counter();
+
+	while remaining > 0
+	{
+		let now = timer::read_counter();
+		// The counter runs down and reloads from the divisor when it hits 0
+		let elapsed = if now <= last { last - now } else { last + divisor - now };
+		if elapsed >= remaining { break }
+		remaining -= elapsed;
+		last = now;
+	}
+}
@@ -0,0 +1,163 @@
+/*
+ * Timer wheel for deferred callbacks
+ *
+ * Timers are hashed into WHEEL_SIZE slots by their deadline tick. Every tick
+ * only the slot for that tick is inspected, timers that belong to a later lap
+ * around the wheel are left alone. Callbacks run in interrupt context.
+ */
+
+use core::prelude::*;
+use collections::vec::Vec;
+use kernel::time;
+use kernel::sync::{Once, IrqLock};
+
+pub type TimerCallback = fn(usize);
+
+const WHEEL_SIZE: usize = 64;
+
+pub struct TimerId(u32);
+
+impl Copy for TimerId {}
+impl Clone for TimerId { fn clone(&self) -> Self { *self } }
+
+struct Timer
+{
+	id: u32,
+	deadline: u64,
+	period: u32,
+	callback: TimerCallback,
+	data: usize
+}
+
+impl Copy for Timer {}
+impl Clone for Timer { fn clone(&self) -> Self { *self } }
+
+struct Wheel
+{
+	slots: Vec<Vec<Timer>>,
+	next_id: u32,
+	processed: u64
+}
+
+static WHEEL: Once<IrqLock<Wheel>> = once!();
+
+pub fn init()
+{
+	let mut slots = Vec::with_capacity(WHEEL_SIZE);
+	for _ in (0 .. WHEEL_SIZE)
+	{
+		slots.push(Vec::new());
+	}
+
+	let processed = time::ticks();
+	WHEEL.call_once(move || IrqLock::new(Wheel { slots: slots, next_id: 1, processed: processed }));
+}
+
+/// Calls `callback(data)` once the tick count reaches `deadline`
+pub fn add_timer(deadline: u64, callback: TimerCallback, data: usize) -> TimerId
+{
+	insert(deadline, 0, callback, data)
+}
+
+/// Calls `callback(data)` every `interval` ticks, starting `interval` ticks from now
+pub fn add_periodic_timer(interval: u32, callback: TimerCallback, data: usize) -> TimerId
+{
+	let interval = if interval == 0 { 1 } else { interval };
+	insert(time::ticks() + interval as u64, interval, callback, data)
+}
+
+/// Calls `callback(data)` once, at least `ms` milliseconds from now
+pub fn add_timer_ms(ms: u32, callback: TimerCallback, data: usize) -> TimerId
+{
+	add_timer(time::ticks() + time::ms_to_ticks(ms) as u64, callback, data)
+}
+
+/// Removes a pending timer. Returns false if it already fired or never existed.
+pub fn cancel_timer(id: TimerId) -> bool
+{
+	let mut w = get_wheel().lock();
+	let TimerId(id) = id;
+	for slot in w.slots.iter_mut()
+	{
+		let position = slot.iter().position(|t| t.id == id);
+		if let Some(index) = position
+		{
+			slot.swap_remove(index);
+			return true;
+		}
+	}
+	false
+}
+
+/// Fires every timer that is due. Called from the timer interrupt.
+pub fn run_timers()
+{
+	let wheel = match WHEEL.get()
+	{
+		Some(wheel) => wheel,
+		None => return,
+	};
+
+	let now = time::ticks();
+	let mut expired = Vec::new();
+	{
+		let mut w = wheel.lock();
+		while w.processed < now
+		{
+			w.processed += 1;
+			let index = (w.processed as u32 as usize) % WHEEL_SIZE;
+			let slot = &mut w.slots[index];
+			let mut i = 0;
+			while i < slot.len()
+			{
+				if slot[i].deadline <= now
+				{
+					expired.push(slot.swap_remove(i));
+				}
+				else
+				{
+					i += 1;
+				}
+			}
+		}
+	}
+
+	// Callbacks may add or cancel timers, so the wheel is not locked here
+	for timer in expired.iter()
+	{
+		if timer.period != 0
+		{
+			let mut w = wheel.lock();
+			// A timer that fell behind counts from now, a slot that has
+			// already passed would only be looked at again a lap later
+			let deadline = timer.deadline + timer.period as u64;
+			let deadline = if deadline <= w.processed { w.processed + timer.period as u64 } else { deadline };
+			let next = Timer { deadline: deadline, .. *timer };
+			w.slots[(next.deadline as u32 as usize) % WHEEL_SIZE].push(next);
+		}
+		(timer.callback)(timer.data);
+	}
+}
+
+fn insert(deadline: u64, period: u32, callback: TimerCallback, data: usize) -> TimerId
+{
+	let mut w = get_wheel().lock();
+	let id = w.next_id;
+	w.next_id += 1;
+
+	// Deadlines in the past fire on the next tick
+	let processed = w.processed;
+	let deadline = if deadline <= processed { processed + 1 } else { deadline };
+	let timer = Timer { id: id, deadline: deadline, period: period, callback: callback, data: data };
+	w.slots[(deadline as u32 as usize) % WHEEL_SIZE].push(timer);
+	TimerId(id)
+}
+
+fn get_wheel() -> &'static IrqLock<Wheel>
+{
+	match WHEEL.get()
+	{
+		Some(wheel) => wheel,
+		None => panic!("Timer wheel used before time::init"),
+	}
+}
@@ -2,10 +2,33 @@
 #![crate_type = "staticlib"]
 #![no_std]
 #![feature(no_std, asm, lang_items)]
-#![feature(core)]
+#![feature(core, alloc, collections, unsafe_destructor)]
 
 #[macro_use] extern crate core;
 extern crate rlibc;
+extern crate alloc;
+#[macro_use] extern crate collections;
+
+// kernel comes first so its macros can be used in platform
+pub mod kernel {
+	// sync first, log builds its statics with irq_lock!
+	#[macro_use] pub mod sync;
+	#[macro_use] pub mod log;
+	pub mod main;
+	pub mod boot;
+	pub mod cmdline;
+	pub mod initrd;
+	pub mod fs;
+	pub mod interrupts;
+	pub mod heap;
+	pub mod time;
+	pub mod thread;
+	pub mod syscall;
+	pub mod elf;
+	pub mod process;
+	mod stdio;
+	mod keyboard;
+}
 
 #[path = "arch/x86/"]
 pub mod platform {
@@ -14,13 +37,10 @@ pub mod platform {
 	pub mod mmu;
 	mod io;
 	pub mod keyboard;
-}
-
-pub mod kernel {
-	pub mod main;
-	pub mod interrupts;
-	mod stdio;
-	mod keyboard;
+	pub mod rtc;
+	pub mod multiboot;
+	pub mod multiboot2;
+	pub mod serial;
 }
 
 #[lang = "stack_exhausted"] extern fn stack_exhausted() {}